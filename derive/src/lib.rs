@@ -0,0 +1,333 @@
+//! Custom derives for `xfg`.
+//!
+//! `#[derive(PassDesc)]` implements `xfg::PassDesc` from `#[pass(...)]` attribute:
+//!
+//! ```ignore
+//! #[derive(Debug, PassDesc)]
+//! #[pass(name = "DrawFlat", colors = 1, depth, vertices(PosColor), bindings = "BINDINGS")]
+//! struct DrawFlat;
+//! ```
+//!
+//! Recognized keys are `name` (type name by default), `sampled`, `storage`, `inputs`, `colors`
+//! (`0` by default), `depth` and `stencil` flags, `vertices` with list of types that implement
+//! `xfg::Vertex` and `bindings` with expression of type
+//! `&'static [&'static [DescriptorSetLayoutBinding]]` (empty by default).
+//! Vertex types other than plain names are given as strings: `vertices(PosColor, "mesh::Pos")`.
+//!
+//! `#[derive(Vertex)]` implements `xfg::Vertex` for `#[repr(C)]` struct.
+//! Formats of the attributes are deduced from the field types (`f32`, `[f32; N]`,
+//! `u32`, `[u32; N]`, `i32` and `[i32; N]`) or set explicitly:
+//!
+//! ```ignore
+//! #[derive(Clone, Copy, Vertex)]
+//! #[repr(C)]
+//! #[vertex(per_instance)]
+//! struct Instance {
+//!     #[vertex(location = 4)]
+//!     transform: [f32; 4],
+//!     #[vertex(format = "Rgba8Unorm")]
+//!     color: [u8; 4],
+//! }
+//! ```
+//!
+
+#![deny(missing_docs)]
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use quote::Tokens;
+use syn::{Attribute, Data, DeriveInput, Expr, Fields, Ident, Lit, Meta, NestedMeta, Type};
+
+/// Derive `xfg::PassDesc` from `#[pass(...)]` attribute.
+#[proc_macro_derive(PassDesc, attributes(pass))]
+pub fn derive_pass_desc(input: TokenStream) -> TokenStream {
+    let input: DeriveInput = syn::parse(input).expect("Failed to parse input");
+    impl_pass_desc(&input).into()
+}
+
+/// Derive `xfg::Vertex` for `#[repr(C)]` struct.
+#[proc_macro_derive(Vertex, attributes(vertex))]
+pub fn derive_vertex(input: TokenStream) -> TokenStream {
+    let input: DeriveInput = syn::parse(input).expect("Failed to parse input");
+    impl_vertex(&input).into()
+}
+
+fn impl_pass_desc(input: &DeriveInput) -> Tokens {
+    let mut name = input.ident.as_ref().to_string();
+    let mut sampled = 0;
+    let mut storage = 0;
+    let mut inputs = 0;
+    let mut colors = 0;
+    let mut depth = false;
+    let mut stencil = false;
+    let mut vertices = Vec::new();
+    let mut bindings = None;
+
+    for meta in nested_metas(&input.attrs, "pass") {
+        match meta {
+            Meta::NameValue(ref value) if value.ident == "name" => name = lit_str(&value.lit),
+            Meta::NameValue(ref value) if value.ident == "sampled" => sampled = lit_int(&value.lit),
+            Meta::NameValue(ref value) if value.ident == "storage" => storage = lit_int(&value.lit),
+            Meta::NameValue(ref value) if value.ident == "inputs" => inputs = lit_int(&value.lit),
+            Meta::NameValue(ref value) if value.ident == "colors" => colors = lit_int(&value.lit),
+            Meta::NameValue(ref value) if value.ident == "bindings" => {
+                let expr: Expr =
+                    syn::parse_str(&lit_str(&value.lit)).expect("`bindings` must be an expression");
+                bindings = Some(expr);
+            }
+            Meta::Word(ref word) if word == "depth" => depth = true,
+            Meta::Word(ref word) if word == "stencil" => stencil = true,
+            Meta::List(ref list) if list.ident == "vertices" => {
+                for nested in list.nested.iter() {
+                    match *nested {
+                        NestedMeta::Meta(Meta::Word(ref ty)) => vertices.push(quote!(#ty)),
+                        NestedMeta::Literal(Lit::Str(ref lit)) => {
+                            let ty: Type = syn::parse_str(&lit.value()).unwrap_or_else(|_| {
+                                panic!("`{}` in `vertices` is not a type", lit.value())
+                            });
+                            vertices.push(quote!(#ty));
+                        }
+                        _ => panic!(
+                            "`vertices` must contain vertex type names or strings with types, \
+                             e.g. `vertices(PosColor, \"mesh::PosNormal\")`"
+                        ),
+                    }
+                }
+            }
+            meta => panic!("Unknown pass attribute `{}`", meta.name()),
+        }
+    }
+
+    let (sampled, storage, inputs, colors) = (
+        sampled as usize,
+        storage as usize,
+        inputs as usize,
+        colors as usize,
+    );
+    let bindings = bindings.map_or_else(|| quote!(&[]), |expr| quote!(#expr));
+    let ident = input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics ::xfg::PassDesc for #ident #ty_generics #where_clause {
+            fn name(&self) -> &str {
+                #name
+            }
+
+            fn sampled(&self) -> usize {
+                #sampled
+            }
+
+            fn storage(&self) -> usize {
+                #storage
+            }
+
+            fn inputs(&self) -> usize {
+                #inputs
+            }
+
+            fn colors(&self) -> usize {
+                #colors
+            }
+
+            fn depth(&self) -> bool {
+                #depth
+            }
+
+            fn stencil(&self) -> bool {
+                #stencil
+            }
+
+            fn vertices(&self) -> &[::xfg::VertexFormat] {
+                const VERTICES: &'static [::xfg::VertexFormat<'static>] = &[
+                    #(<#vertices as ::xfg::Vertex>::FORMAT,)*
+                ];
+                VERTICES
+            }
+
+            fn bindings(&self) -> &[&[::xfg::__derive::DescriptorSetLayoutBinding]] {
+                #bindings
+            }
+        }
+    }
+}
+
+fn impl_vertex(input: &DeriveInput) -> Tokens {
+    let repr_c = input.attrs.iter().any(|attr| match attr.interpret_meta() {
+        Some(Meta::List(ref list)) if list.ident == "repr" => {
+            list.nested.iter().any(|nested| match *nested {
+                NestedMeta::Meta(Meta::Word(ref word)) => word == "C",
+                _ => false,
+            })
+        }
+        _ => false,
+    });
+    assert!(
+        repr_c,
+        "`Vertex` can be derived only for `#[repr(C)]` structs"
+    );
+
+    let mut rate = 0u8;
+    for meta in nested_metas(&input.attrs, "vertex") {
+        match meta {
+            Meta::Word(ref word) if word == "per_instance" => rate = 1,
+            Meta::NameValue(ref value) if value.ident == "rate" => rate = lit_int(&value.lit) as u8,
+            meta => panic!("Unknown vertex attribute `{}`", meta.name()),
+        }
+    }
+
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => fields.named.iter().cloned().collect::<Vec<_>>(),
+            Fields::Unnamed(ref fields) => fields.unnamed.iter().cloned().collect::<Vec<_>>(),
+            Fields::Unit => Vec::new(),
+        },
+        _ => panic!("`Vertex` can be derived only for structs"),
+    };
+
+    // Offsets follow `#[repr(C)]` layout: each field starts at the end of the previous one
+    // rounded up to the field alignment
+    let mut offset = quote!(0usize);
+    let mut attributes = Vec::new();
+    for field in fields {
+        let mut format = None;
+        let mut location = quote!(None);
+        for meta in nested_metas(&field.attrs, "vertex") {
+            match meta {
+                Meta::NameValue(ref value) if value.ident == "format" => {
+                    format = Some(lit_str(&value.lit));
+                }
+                Meta::NameValue(ref value) if value.ident == "location" => {
+                    let value = lit_int(&value.lit) as u32;
+                    location = quote!(Some(#value));
+                }
+                meta => panic!("Unknown vertex attribute `{}`", meta.name()),
+            }
+        }
+
+        let format = Ident::from(format.unwrap_or_else(|| deduce_format(&field.ty)));
+        let ty = &field.ty;
+        let align = quote!(::std::mem::align_of::<#ty>());
+        offset = quote!(((#offset + #align - 1) & !(#align - 1)));
+        attributes.push(quote! {
+            ::xfg::VertexAttribute {
+                location: #location,
+                element: ::xfg::__derive::Element {
+                    format: ::xfg::__derive::Format::#format,
+                    offset: #offset as u32,
+                },
+            }
+        });
+        offset = quote!((#offset + ::std::mem::size_of::<#ty>()));
+    }
+
+    let ident = input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics ::xfg::Vertex for #ident #ty_generics #where_clause {
+            const FORMAT: ::xfg::VertexFormat<'static> = ::xfg::VertexFormat {
+                attributes: &[#(#attributes,)*],
+                stride: ::std::mem::size_of::<Self>() as u32,
+                rate: #rate,
+            };
+        }
+    }
+}
+
+/// Collect metas nested into attributes like `#[name(...)]`.
+fn nested_metas(attrs: &[Attribute], name: &str) -> Vec<Meta> {
+    attrs
+        .iter()
+        .filter_map(|attr| {
+            let meta = attr.interpret_meta();
+            let named = attr.path.segments.len() == 1
+                && attr.path.segments.iter().all(|segment| segment.ident == name);
+            if named && meta.is_none() {
+                panic!(
+                    "`#[{}(...)]` attribute can't be parsed, paths and expressions must be \
+                     given as strings: `{}`",
+                    name, attr.tts
+                );
+            }
+            meta
+        })
+        .filter_map(|meta| match meta {
+            Meta::List(ref list) if list.ident == name => Some(
+                list.nested
+                    .iter()
+                    .map(|nested| match *nested {
+                        NestedMeta::Meta(ref meta) => meta.clone(),
+                        NestedMeta::Literal(_) => panic!("Unexpected literal in `{}`", name),
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+fn lit_str(lit: &Lit) -> String {
+    match *lit {
+        Lit::Str(ref lit) => lit.value(),
+        _ => panic!("String literal expected"),
+    }
+}
+
+fn lit_int(lit: &Lit) -> u64 {
+    match *lit {
+        Lit::Int(ref lit) => lit.value(),
+        _ => panic!("Integer literal expected"),
+    }
+}
+
+/// Deduce attribute format from field type.
+fn deduce_format(ty: &Type) -> String {
+    let (scalar, count) = match *ty {
+        Type::Path(ref path) => (scalar_name(path), 1),
+        Type::Array(ref array) => match *array.elem {
+            Type::Path(ref path) => {
+                let count = match array.len {
+                    Expr::Lit(ref lit) => lit_int(&lit.lit),
+                    _ => panic!("Array length of vertex attribute must be literal"),
+                };
+                (scalar_name(path), count)
+            }
+            _ => panic!("Format of vertex attribute can't be deduced from type"),
+        },
+        _ => panic!("Format of vertex attribute can't be deduced from type"),
+    };
+
+    let components = match count {
+        1 => "R",
+        2 => "Rg",
+        3 => "Rgb",
+        4 => "Rgba",
+        _ => panic!("Vertex attribute can't have more than 4 components"),
+    };
+    let kind = match scalar.as_ref() {
+        "f32" => "32Float",
+        "u32" => "32Uint",
+        "i32" => "32Int",
+        _ => panic!(
+            "Format of vertex attribute can't be deduced from `{}`",
+            scalar
+        ),
+    };
+    format!("{}{}", components, kind)
+}
+
+fn scalar_name(path: &syn::TypePath) -> String {
+    path.path
+        .segments
+        .iter()
+        .last()
+        .map(|segment| segment.ident.as_ref().to_string())
+        .expect("Empty type path")
+}
@@ -0,0 +1,46 @@
+extern crate xfg;
+#[macro_use]
+extern crate xfg_derive;
+
+use xfg::{PassDesc, Vertex};
+
+#[derive(Clone, Copy, Vertex)]
+#[repr(C)]
+struct PosColor {
+    pos: [f32; 3],
+    color: [f32; 4],
+}
+
+mod instance {
+    #[derive(Clone, Copy, Vertex)]
+    #[repr(C)]
+    #[vertex(per_instance)]
+    pub struct Offset {
+        pub offset: [f32; 2],
+    }
+}
+
+#[derive(Debug, PassDesc)]
+#[pass(name = "DrawFlat", inputs = 1, colors = 2, depth, vertices(PosColor, "instance::Offset"))]
+struct DrawFlat;
+
+#[derive(Debug, PassDesc)]
+struct Empty;
+
+#[test]
+fn test_pass_desc() {
+    assert_eq!(DrawFlat.name(), "DrawFlat");
+    assert_eq!(DrawFlat.inputs(), 1);
+    assert_eq!(DrawFlat.colors(), 2);
+    assert!(DrawFlat.depth());
+    assert!(!DrawFlat.stencil());
+    assert_eq!(DrawFlat.vertices().len(), 2);
+    assert_eq!(DrawFlat.vertices()[0].stride, PosColor::FORMAT.stride);
+    assert_eq!(DrawFlat.vertices()[1].rate, 1);
+
+    assert_eq!(Empty.name(), "Empty");
+    assert_eq!(Empty.inputs(), 0);
+    assert_eq!(Empty.colors(), 0);
+    assert!(!Empty.depth());
+    assert!(Empty.vertices().is_empty());
+}
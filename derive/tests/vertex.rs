@@ -0,0 +1,38 @@
+extern crate xfg;
+#[macro_use]
+extern crate xfg_derive;
+
+use xfg::Vertex;
+
+#[derive(Clone, Copy, Vertex)]
+#[repr(C)]
+struct PosColor {
+    pos: [f32; 3],
+    color: [f32; 4],
+}
+
+#[derive(Clone, Copy, Vertex)]
+#[repr(C)]
+struct Padded {
+    #[vertex(format = "R8Unorm")]
+    flag: u8,
+    pos: [f32; 3],
+    #[vertex(format = "R8Unorm")]
+    layer: u8,
+}
+
+#[test]
+fn test_vertex_offsets() {
+    let offsets = |attributes: &[xfg::VertexAttribute]| {
+        attributes
+            .iter()
+            .map(|attribute| attribute.element.offset)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(offsets(PosColor::FORMAT.attributes), vec![0, 12]);
+    assert_eq!(PosColor::FORMAT.stride, 28);
+
+    assert_eq!(offsets(Padded::FORMAT.attributes), vec![0, 4, 16]);
+    assert_eq!(Padded::FORMAT.stride, 20);
+}
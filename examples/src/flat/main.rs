@@ -2,7 +2,8 @@
 #![deny(unused_must_use)]
 #![allow(dead_code)]
 
-extern crate smallvec;
+#[macro_use]
+extern crate xfg_derive;
 extern crate xfg_examples;
 use xfg_examples::*;
 
@@ -19,13 +20,12 @@ use gfx_hal::device::ShaderError;
 use gfx_hal::format::Format;
 use gfx_hal::memory::{cast_slice, Pod};
 use gfx_hal::pso::{DescriptorSetLayoutBinding, DescriptorSetWrite, DescriptorType,
-                   DescriptorWrite, ElemStride, Element, EntryPoint, GraphicsShaderSet,
+                   DescriptorWrite, EntryPoint, GraphicsShaderSet,
                    ShaderStageFlags, VertexBufferSet};
 use gfx_hal::queue::Transfer;
 use mem::{Block, Factory, SmartAllocator};
-use smallvec::SmallVec;
 use xfg::{ColorAttachment, DepthStencilAttachment, DescriptorPool, GraphBuilder, Pass, PassDesc,
-          PassShaders};
+          PassShaders, ShaderModules};
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -37,67 +37,29 @@ struct TrProjView {
 
 unsafe impl Pod for TrProjView {}
 
-#[derive(Debug)]
-struct DrawFlat;
-
-impl PassDesc for DrawFlat {
-    fn name(&self) -> &str {
-        "DrawFlat"
-    }
-
-    fn sampled(&self) -> usize {
-        0
-    }
-
-    fn storage(&self) -> usize {
-        0
-    }
-
-    fn inputs(&self) -> usize {
-        0
-    }
-
-    fn colors(&self) -> usize {
-        1
-    }
-
-    fn depth(&self) -> bool {
-        true
-    }
+#[repr(C)]
+#[derive(Clone, Copy, Vertex)]
+struct PosColor {
+    position: [f32; 3],
+    color: [f32; 4],
+}
 
-    fn stencil(&self) -> bool {
-        false
-    }
+unsafe impl Pod for PosColor {}
 
-    fn vertices(&self) -> &[(&[Element<Format>], ElemStride)] {
-        &[
-            (
-                &[
-                    Element {
-                        format: Format::Rgb32Float,
-                        offset: 0,
-                    },
-                    Element {
-                        format: Format::Rgba32Float,
-                        offset: 12,
-                    },
-                ],
-                28,
-            ),
-        ]
-    }
+const BINDINGS: &[&[DescriptorSetLayoutBinding]] = &[
+    &[
+        DescriptorSetLayoutBinding {
+            binding: 0,
+            ty: DescriptorType::UniformBuffer,
+            count: 1,
+            stage_flags: ShaderStageFlags::VERTEX,
+        },
+    ],
+];
 
-    fn bindings(&self) -> &[DescriptorSetLayoutBinding] {
-        &[
-            DescriptorSetLayoutBinding {
-                binding: 0,
-                ty: DescriptorType::UniformBuffer,
-                count: 1,
-                stage_flags: ShaderStageFlags::VERTEX,
-            },
-        ]
-    }
-}
+#[derive(Debug, PassDesc)]
+#[pass(colors = 1, depth, vertices(PosColor), bindings = "BINDINGS")]
+struct DrawFlat;
 
 impl<B> PassShaders<B> for DrawFlat
 where
@@ -105,12 +67,11 @@ where
 {
     fn shaders<'a>(
         &self,
-        shaders: &'a mut SmallVec<[B::ShaderModule; 5]>,
+        shaders: &'a mut ShaderModules<B>,
         device: &B::Device,
     ) -> Result<GraphicsShaderSet<'a, B>, ShaderError> {
-        shaders.clear();
-        shaders.push(device.create_shader_module(include_bytes!("vert.spv"))?);
-        shaders.push(device.create_shader_module(include_bytes!("frag.spv"))?);
+        shaders.create(include_bytes!("vert.spv"), device)?;
+        shaders.create(include_bytes!("frag.spv"), device)?;
 
         Ok(GraphicsShaderSet {
             vertex: EntryPoint {
@@ -136,10 +97,11 @@ where
 {
     fn prepare<'a>(
         &mut self,
-        pool: &mut DescriptorPool<B>,
+        pools: &mut [DescriptorPool<B>],
         cbuf: &mut CommandBuffer<B, Transfer>,
         device: &B::Device,
         _inputs: &[&B::Image],
+        _samplers: &[&B::Sampler],
         frame: usize,
         scene: &mut Scene<B>,
     ) {
@@ -167,7 +129,7 @@ where
                         Usage::UNIFORM | Usage::TRANSFER_DST,
                     )
                     .unwrap();
-                let set = pool.allocate(device);
+                let set = pools[0].allocate(device);
                 device.write_descriptor_sets(&[
                     DescriptorSetWrite {
                         set: &set,
@@ -178,7 +140,6 @@ where
                 ]);
                 Cache {
                     uniforms: vec![buffer],
-                    views: Vec::new(),
                     set,
                 }
             });
@@ -189,9 +150,11 @@ where
     fn draw_inline<'a>(
         &mut self,
         layout: &B::PipelineLayout,
+        _pipelines: &[B::GraphicsPipeline],
         mut encoder: RenderPassInlineEncoder<B, Primary>,
         _device: &B::Device,
         _inputs: &[&B::Image],
+        _samplers: &[&B::Sampler],
         frame: usize,
         scene: &Scene<B>,
     ) {
@@ -211,11 +174,16 @@ where
         }
     }
 
-    fn cleanup(&mut self, pool: &mut DescriptorPool<B>, device: &B::Device, scene: &mut Scene<B>) {
+    fn cleanup(
+        &mut self,
+        pools: &mut [DescriptorPool<B>],
+        device: &B::Device,
+        scene: &mut Scene<B>,
+    ) {
         for object in &mut scene.objects {
             for cache in object.cache.drain(..) {
                 if let Some(cache) = cache {
-                    pool.free(cache.set);
+                    pools[0].free(cache.set);
                     for uniform in cache.uniforms {
                         scene.allocator.destroy_buffer(device, uniform);
                     }
@@ -229,15 +197,6 @@ fn create_cube<B>(device: &B::Device, factory: &mut SmartAllocator<B>) -> Mesh<B
 where
     B: Backend,
 {
-    #[repr(C)]
-    #[derive(Copy, Clone)]
-    struct PosColor {
-        position: [f32; 3],
-        color: [f32; 4],
-    }
-
-    unsafe impl Pod for PosColor {}
-
     let vertices = vec![
         // Right
         PosColor {
@@ -427,7 +386,8 @@ fn graph(surface_format: Format, graph: &mut GraphBuilder<DrawFlat>) {
 
     let pass = DrawFlat.build().with_color(color).with_depth_stencil(depth);
 
-    graph.add_pass(pass).set_present(color);
+    graph.add_pass(pass);
+    graph.set_present(color);
 }
 
 fn fill<B>(scene: &mut Scene<B>, device: &B::Device)
@@ -45,7 +45,6 @@ pub const REQUEST_CPU_VISIBLE: (Type, Properties) = (Type::General, Properties::
 
 pub struct Cache<B: Backend> {
     pub uniforms: Vec<Buffer<B>>,
-    pub views: Vec<B::ImageView>,
     pub set: B::DescriptorSet,
 }
 
@@ -82,6 +81,7 @@ pub struct Scene<B: Backend, T = ()> {
     pub lights: Vec<Light<B>>,
     pub camera: Camera,
     pub allocator: SmartAllocator<B>,
+    pub cache: Vec<Option<Cache<B>>>,
 }
 
 #[cfg(not(any(feature = "dx12", feature = "metal", feature = "gl", feature = "vulkan")))]
@@ -232,6 +232,7 @@ where
             projection,
         },
         allocator,
+        cache: Vec::new(),
     };
 
     // fill scene
@@ -7,7 +7,7 @@ extern crate xfg_examples;
 use xfg_examples::*;
 
 use std::borrow::Borrow;
-use std::ops::{Add, BitOr, Sub};
+use std::ops::{Add, BitOr, Range, Sub};
 use std::sync::Arc;
 
 use cgmath::{EuclideanSpace, Matrix4, Point3, Transform};
@@ -20,23 +20,30 @@ use gfx_hal::device::ShaderError;
 use gfx_hal::format::Format;
 use gfx_hal::memory::{cast_slice, Pod};
 use gfx_hal::pso::{DescriptorSetLayoutBinding, DescriptorSetWrite, DescriptorType,
-                   DescriptorWrite, ElemStride, Element, EntryPoint, GraphicsShaderSet,
+                   DescriptorWrite, Element, EntryPoint, GraphicsShaderSet,
                    ShaderStageFlags, VertexBufferSet};
 use gfx_hal::queue::Transfer;
 use mem::{Block, Factory, SmartAllocator};
 use smallvec::SmallVec;
 use xfg::{ColorAttachment, DepthStencilAttachment, DescriptorPool, GraphBuilder, Pass, PassDesc,
-          PassShaders};
+          PassShaders, ShaderModules, VertexAttribute, VertexFormat};
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug, PartialEq)]
-struct VertexArgs {
+struct FrameVertexArgs {
     proj: Matrix4<f32>,
     view: Matrix4<f32>,
+}
+
+unsafe impl Pod for FrameVertexArgs {}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ObjectVertexArgs {
     model: Matrix4<f32>,
 }
 
-unsafe impl Pod for VertexArgs {}
+unsafe impl Pod for ObjectVertexArgs {}
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -67,21 +74,20 @@ struct ObjectData {
     ambient_occlusion: f32,
 }
 
+// Object data matches `ObjectArgs` of the fragment shader
+unsafe impl Pod for ObjectData {}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug, PartialEq)]
-struct FragmentArgs {
+struct FrameFragmentArgs {
     plight: [PointLight; 32],
     camera_position: [f32; 3],
     point_light_count: u32,
     ambient_light: [f32; 3],
-    ambient_occlusion: f32,
-    albedo: [f32; 3],
-    metallic: f32,
-    emission: [f32; 3],
-    roughness: f32,
+    pad: f32,
 }
 
-unsafe impl Pod for FragmentArgs {}
+unsafe impl Pod for FrameFragmentArgs {}
 
 fn pad(value: [f32; 3], pad: f32) -> [f32; 4] {
     [value[0], value[1], value[2], pad]
@@ -119,26 +125,34 @@ impl PassDesc for DrawPbm {
         false
     }
 
-    fn vertices(&self) -> &[(&[Element<Format>], ElemStride)] {
+    fn vertices(&self) -> &[VertexFormat] {
         &[
-            (
-                &[
-                    Element {
-                        format: Format::Rgb32Float,
-                        offset: 0,
+            VertexFormat {
+                attributes: &[
+                    VertexAttribute {
+                        location: None,
+                        element: Element {
+                            format: Format::Rgb32Float,
+                            offset: 0,
+                        },
                     },
-                    Element {
-                        format: Format::Rgb32Float,
-                        offset: 12,
+                    VertexAttribute {
+                        location: None,
+                        element: Element {
+                            format: Format::Rgb32Float,
+                            offset: 12,
+                        },
                     },
                 ],
-                24,
-            ),
+                stride: 24,
+                rate: 0,
+            },
         ]
     }
 
-    fn bindings(&self) -> &[DescriptorSetLayoutBinding] {
-        &[
+    // Set 0 is updated once per frame, set 1 once per object
+    fn bindings(&self) -> &[&[DescriptorSetLayoutBinding]] {
+        const UNIFORMS: &[DescriptorSetLayoutBinding] = &[
             DescriptorSetLayoutBinding {
                 binding: 0,
                 ty: DescriptorType::UniformBuffer,
@@ -151,7 +165,8 @@ impl PassDesc for DrawPbm {
                 count: 1,
                 stage_flags: ShaderStageFlags::FRAGMENT,
             },
-        ]
+        ];
+        &[UNIFORMS, UNIFORMS]
     }
 }
 
@@ -161,12 +176,11 @@ where
 {
     fn shaders<'a>(
         &self,
-        shaders: &'a mut SmallVec<[B::ShaderModule; 5]>,
+        shaders: &'a mut ShaderModules<B>,
         device: &B::Device,
     ) -> Result<GraphicsShaderSet<'a, B>, ShaderError> {
-        shaders.clear();
-        shaders.push(device.create_shader_module(include_bytes!("vert.spv"))?);
-        shaders.push(device.create_shader_module(include_bytes!("frag.spv"))?);
+        shaders.create(include_bytes!("vert.spv"), device)?;
+        shaders.create(include_bytes!("frag.spv"), device)?;
 
         Ok(GraphicsShaderSet {
             vertex: EntryPoint {
@@ -192,10 +206,11 @@ where
 {
     fn prepare<'a>(
         &mut self,
-        pool: &mut DescriptorPool<B>,
+        pools: &mut [DescriptorPool<B>],
         cbuf: &mut CommandBuffer<B, Transfer>,
         device: &B::Device,
         _inputs: &[&B::Image],
+        _samplers: &[&B::Sampler],
         frame: usize,
         scene: &mut Scene<B, ObjectData>,
     ) {
@@ -206,76 +221,72 @@ where
             .transform
             .transform_point(Point3::origin())
             .into();
-        // Update uniform cache
-        for obj in &mut scene.objects {
-            let vertex_args = VertexArgs {
-                model: obj.transform,
-                proj: scene.camera.projection,
-                view,
-            };
 
-            let mut plight: [PointLight; 32] = unsafe { ::std::mem::zeroed() };
-            let mut point_light_count = 0;
+        let mut plight: [PointLight; 32] = unsafe { ::std::mem::zeroed() };
+        let mut point_light_count = 0;
 
-            for light in &scene.lights {
-                plight[point_light_count].position =
-                    light.transform.transform_point(Point3::origin()).into();
-                plight[point_light_count].color = pad(light.color, 1.0);
-                point_light_count += 1;
-            }
+        for light in &scene.lights {
+            plight[point_light_count].position =
+                light.transform.transform_point(Point3::origin()).into();
+            plight[point_light_count].color = pad(light.color, 1.0);
+            point_light_count += 1;
+        }
 
-            let fragment_args = FragmentArgs {
-                plight,
-                camera_position,
-                point_light_count: point_light_count as u32,
-                ambient_light: scene.ambient.0,
-                ambient_occlusion: obj.data.ambient_occlusion,
-                albedo: obj.data.albedo,
-                metallic: obj.data.metallic,
-                emission: obj.data.emission,
-                roughness: obj.data.roughness,
+        // Update uniforms shared by all objects
+        let frame_vertex_args = FrameVertexArgs {
+            proj: scene.camera.projection,
+            view,
+        };
+        let frame_fragment_args = FrameFragmentArgs {
+            plight,
+            camera_position,
+            point_light_count: point_light_count as u32,
+            ambient_light: scene.ambient.0,
+            pad: 0.0,
+        };
+        let (vertex_args_range, fragment_args_range) =
+            uniform_ranges::<FrameVertexArgs, FrameFragmentArgs>();
+
+        let grow = (scene.cache.len()..frame + 1).map(|_| None);
+        scene.cache.extend(grow);
+        let cache = scene.cache[frame].get_or_insert_with(|| {
+            create_uniforms(
+                &mut pools[0],
+                allocator,
+                vertex_args_range.clone(),
+                fragment_args_range.clone(),
+                device,
+            )
+        });
+        cbuf.update_buffer(
+            cache.uniforms[0].borrow(),
+            vertex_args_range.start,
+            cast_slice(&[frame_vertex_args]),
+        );
+        cbuf.update_buffer(
+            cache.uniforms[0].borrow(),
+            fragment_args_range.start,
+            cast_slice(&[frame_fragment_args]),
+        );
+
+        // Update uniform cache of each object
+        let (vertex_args_range, fragment_args_range) =
+            uniform_ranges::<ObjectVertexArgs, ObjectData>();
+        for obj in &mut scene.objects {
+            let vertex_args = ObjectVertexArgs {
+                model: obj.transform,
             };
 
-            let vertex_args_range = 0..::std::mem::size_of::<VertexArgs>() as u64;
-            let fragment_args_offset = shift_for_alignment(256, vertex_args_range.end);
-            let fragment_args_range = fragment_args_offset
-                ..fragment_args_offset + ::std::mem::size_of::<FragmentArgs>() as u64;
-
             let grow = (obj.cache.len()..frame + 1).map(|_| None);
             obj.cache.extend(grow);
             let cache = obj.cache[frame].get_or_insert_with(|| {
-                let buffer = allocator
-                    .create_buffer(
-                        device,
-                        REQUEST_DEVICE_LOCAL,
-                        fragment_args_range.end,
-                        Usage::UNIFORM | Usage::TRANSFER_DST,
-                    )
-                    .unwrap();
-                let set = pool.allocate(device);
-                device.write_descriptor_sets(&[
-                    DescriptorSetWrite {
-                        set: &set,
-                        binding: 0,
-                        array_offset: 0,
-                        write: DescriptorWrite::UniformBuffer(&[
-                            (buffer.borrow(), vertex_args_range.clone()),
-                        ]),
-                    },
-                    DescriptorSetWrite {
-                        set: &set,
-                        binding: 1,
-                        array_offset: 0,
-                        write: DescriptorWrite::UniformBuffer(&[
-                            (buffer.borrow(), fragment_args_range.clone()),
-                        ]),
-                    },
-                ]);
-                Cache {
-                    uniforms: vec![buffer],
-                    views: Vec::new(),
-                    set,
-                }
+                create_uniforms(
+                    &mut pools[1],
+                    allocator,
+                    vertex_args_range.clone(),
+                    fragment_args_range.clone(),
+                    device,
+                )
             });
             cbuf.update_buffer(
                 cache.uniforms[0].borrow(),
@@ -285,7 +296,7 @@ where
             cbuf.update_buffer(
                 cache.uniforms[0].borrow(),
                 fragment_args_range.start,
-                cast_slice(&[fragment_args]),
+                cast_slice(&[obj.data]),
             );
         }
     }
@@ -293,16 +304,23 @@ where
     fn draw_inline<'a>(
         &mut self,
         layout: &B::PipelineLayout,
+        _pipelines: &[B::GraphicsPipeline],
         mut encoder: RenderPassInlineEncoder<B, Primary>,
         _device: &B::Device,
         _inputs: &[&B::Image],
+        _samplers: &[&B::Sampler],
         frame: usize,
         scene: &Scene<B, ObjectData>,
     ) {
+        encoder.bind_graphics_descriptor_sets(
+            layout,
+            0,
+            Some(&scene.cache[frame].as_ref().unwrap().set),
+        );
         for object in &scene.objects {
             encoder.bind_graphics_descriptor_sets(
                 layout,
-                0,
+                1,
                 Some(&object.cache[frame].as_ref().unwrap().set),
             );
             encoder.bind_index_buffer(IndexBufferView {
@@ -317,14 +335,23 @@ where
 
     fn cleanup(
         &mut self,
-        pool: &mut DescriptorPool<B>,
+        pools: &mut [DescriptorPool<B>],
         device: &B::Device,
         scene: &mut Scene<B, ObjectData>,
     ) {
+        for cache in scene.cache.drain(..) {
+            if let Some(cache) = cache {
+                pools[0].free(cache.set);
+                for uniform in cache.uniforms {
+                    scene.allocator.destroy_buffer(device, uniform);
+                }
+            }
+        }
+
         for object in &mut scene.objects {
             for cache in object.cache.drain(..) {
                 if let Some(cache) = cache {
-                    pool.free(cache.set);
+                    pools[1].free(cache.set);
                     for uniform in cache.uniforms {
                         scene.allocator.destroy_buffer(device, uniform);
                     }
@@ -334,6 +361,55 @@ where
     }
 }
 
+/// Ranges of vertex and fragment uniforms placed in a single buffer.
+fn uniform_ranges<V, F>() -> (Range<u64>, Range<u64>) {
+    let vertex_args_range = 0..::std::mem::size_of::<V>() as u64;
+    let fragment_args_offset = shift_for_alignment(256, vertex_args_range.end);
+    let fragment_args_range =
+        fragment_args_offset..fragment_args_offset + ::std::mem::size_of::<F>() as u64;
+    (vertex_args_range, fragment_args_range)
+}
+
+/// Create uniform buffer and descriptor set with vertex and fragment uniforms bound to it.
+fn create_uniforms<B>(
+    pool: &mut DescriptorPool<B>,
+    allocator: &mut SmartAllocator<B>,
+    vertex_args_range: Range<u64>,
+    fragment_args_range: Range<u64>,
+    device: &B::Device,
+) -> Cache<B>
+where
+    B: Backend,
+{
+    let buffer = allocator
+        .create_buffer(
+            device,
+            REQUEST_DEVICE_LOCAL,
+            fragment_args_range.end,
+            Usage::UNIFORM | Usage::TRANSFER_DST,
+        )
+        .unwrap();
+    let set = pool.allocate(device);
+    device.write_descriptor_sets(&[
+        DescriptorSetWrite {
+            set: &set,
+            binding: 0,
+            array_offset: 0,
+            write: DescriptorWrite::UniformBuffer(&[(buffer.borrow(), vertex_args_range)]),
+        },
+        DescriptorSetWrite {
+            set: &set,
+            binding: 1,
+            array_offset: 0,
+            write: DescriptorWrite::UniformBuffer(&[(buffer.borrow(), fragment_args_range)]),
+        },
+    ]);
+    Cache {
+        uniforms: vec![buffer],
+        set,
+    }
+}
+
 fn graph<'a>(surface_format: Format, graph: &'a mut GraphBuilder<DrawPbm>) {
     let color = graph.add_attachment(
         ColorAttachment::new(surface_format).with_clear(ClearColor::Float([0.0, 0.0, 0.0, 1.0])),
@@ -344,7 +420,8 @@ fn graph<'a>(surface_format: Format, graph: &'a mut GraphBuilder<DrawPbm>) {
 
     let pass = DrawPbm.build().with_color(color).with_depth_stencil(depth);
 
-    graph.add_pass(pass).set_present(color);
+    graph.add_pass(pass);
+    graph.set_present(color);
 }
 
 fn fill<B>(scene: &mut Scene<B, ObjectData>, device: &B::Device)
@@ -13,16 +13,20 @@ struct DirectionalLight {
     float _pad;
 };
 
-layout(binding = 1, set = 0) uniform FragmentArgs {
+layout(binding = 1, set = 0) uniform FrameArgs {
     PointLight plight[32];
     vec3 camera_position;
     uint point_light_count;
     vec3 ambient_light;
-    float ambient_occlusion;
+    float _pad;
+};
+
+layout(binding = 1, set = 1) uniform ObjectArgs {
     vec3 albedo;
     float metallic;
     vec3 emission;
     float roughness;
+    float ambient_occlusion;
 };
 
 layout(location = 0) in VertexData {
@@ -64,7 +68,7 @@ void main() {
     vec3 fresnel_base = mix(vec3(0.04), albedo, metallic);
 
     vec3 lighted = vec3(0.0);
-    for (int i = 0; i < min(point_light_count, 32); i++) {
+    for (uint i = 0; i < min(point_light_count, 32u); i++) {
         vec3 view_direction = normalize(camera_position - vertex.position.xyz);
         vec3 light_direction = normalize(plight[i].position.xyz - vertex.position.xyz);
         float intensity = 1.0 / dot(light_direction, light_direction);
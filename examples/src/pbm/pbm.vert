@@ -1,9 +1,12 @@
 #version 450 core
 #extension GL_ARB_separate_shader_objects : enable
 
-layout(binding=0, set=0) uniform VertexArgs {
+layout(binding = 0, set = 0) uniform FrameArgs {
     mat4 proj;
     mat4 view;
+};
+
+layout(binding = 0, set = 1) uniform ObjectArgs {
     mat4 model;
 };
 
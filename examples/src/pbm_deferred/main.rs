@@ -17,17 +17,17 @@ use gfx_hal::buffer::{IndexBufferView, Usage};
 use gfx_hal::command::{ClearColor, ClearDepthStencil, CommandBuffer, Primary,
                        RenderPassInlineEncoder};
 use gfx_hal::device::ShaderError;
-use gfx_hal::format::{Aspects, Format, Swizzle};
-use gfx_hal::image::{Access, ImageLayout, SubresourceRange};
-use gfx_hal::memory::{cast_slice, Barrier, Dependencies, Pod};
-use gfx_hal::pso::{BlendState, ColorBlendDesc, ColorMask, DescriptorSetLayoutBinding,
-                   DescriptorSetWrite, DescriptorType, DescriptorWrite, ElemStride, Element,
-                   EntryPoint, GraphicsShaderSet, PipelineStage, ShaderStageFlags, VertexBufferSet};
+use gfx_hal::format::Format;
+use gfx_hal::memory::{cast_slice, Pod};
+use gfx_hal::pso::{BlendState, ColorBlendDesc, ColorMask, DescriptorBinding,
+                   DescriptorSetLayoutBinding, DescriptorSetWrite, DescriptorType,
+                   DescriptorWrite, Element, EntryPoint, GraphicsShaderSet, ShaderStageFlags,
+                   VertexBufferSet};
 use gfx_hal::queue::Transfer;
 use mem::{Block, Factory, SmartAllocator};
 use smallvec::SmallVec;
 use xfg::{ColorAttachment, DepthStencilAttachment, DescriptorPool, GraphBuilder, Pass, PassDesc,
-          PassShaders};
+          PassShaders, ShaderModules, VertexAttribute, VertexFormat};
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -79,38 +79,47 @@ impl PassDesc for DrawPbmPrepare {
         false
     }
 
-    fn vertices(&self) -> &[(&[Element<Format>], ElemStride)] {
+    fn vertices(&self) -> &[VertexFormat] {
         &[
-            (
-                &[
-                    Element {
-                        format: Format::Rgb32Float,
-                        offset: 0,
+            VertexFormat {
+                attributes: &[
+                    VertexAttribute {
+                        location: None,
+                        element: Element {
+                            format: Format::Rgb32Float,
+                            offset: 0,
+                        },
                     },
-                    Element {
-                        format: Format::Rgb32Float,
-                        offset: 12,
+                    VertexAttribute {
+                        location: None,
+                        element: Element {
+                            format: Format::Rgb32Float,
+                            offset: 12,
+                        },
                     },
                 ],
-                24,
-            ),
+                stride: 24,
+                rate: 0,
+            },
         ]
     }
 
-    fn bindings(&self) -> &[DescriptorSetLayoutBinding] {
+    fn bindings(&self) -> &[&[DescriptorSetLayoutBinding]] {
         &[
-            DescriptorSetLayoutBinding {
-                binding: 0,
-                ty: DescriptorType::UniformBuffer,
-                count: 1,
-                stage_flags: ShaderStageFlags::VERTEX,
-            },
-            DescriptorSetLayoutBinding {
-                binding: 1,
-                ty: DescriptorType::UniformBuffer,
-                count: 1,
-                stage_flags: ShaderStageFlags::FRAGMENT,
-            },
+            &[
+                DescriptorSetLayoutBinding {
+                    binding: 0,
+                    ty: DescriptorType::UniformBuffer,
+                    count: 1,
+                    stage_flags: ShaderStageFlags::VERTEX,
+                },
+                DescriptorSetLayoutBinding {
+                    binding: 1,
+                    ty: DescriptorType::UniformBuffer,
+                    count: 1,
+                    stage_flags: ShaderStageFlags::FRAGMENT,
+                },
+            ],
         ]
     }
 }
@@ -121,12 +130,11 @@ where
 {
     fn shaders<'a>(
         &self,
-        shaders: &'a mut SmallVec<[B::ShaderModule; 5]>,
+        shaders: &'a mut ShaderModules<B>,
         device: &B::Device,
     ) -> Result<GraphicsShaderSet<'a, B>, ShaderError> {
-        shaders.clear();
-        shaders.push(device.create_shader_module(include_bytes!("first.vert.spv"))?);
-        shaders.push(device.create_shader_module(include_bytes!("first.frag.spv"))?);
+        shaders.create(include_bytes!("first.vert.spv"), device)?;
+        shaders.create(include_bytes!("first.frag.spv"), device)?;
 
         Ok(GraphicsShaderSet {
             vertex: EntryPoint {
@@ -152,10 +160,11 @@ where
 {
     fn prepare<'a>(
         &mut self,
-        pool: &mut DescriptorPool<B>,
+        pools: &mut [DescriptorPool<B>],
         cbuf: &mut CommandBuffer<B, Transfer>,
         device: &B::Device,
         _inputs: &[&B::Image],
+        _samplers: &[&B::Sampler],
         frame: usize,
         scene: &mut Scene<B, ObjectData>,
     ) {
@@ -215,7 +224,7 @@ where
                         Usage::UNIFORM | Usage::TRANSFER_DST,
                     )
                     .unwrap();
-                let set = pool.allocate(device);
+                let set = pools[0].allocate(device);
                 device.write_descriptor_sets(&[
                     DescriptorSetWrite {
                         set: &set,
@@ -236,7 +245,6 @@ where
                 ]);
                 Cache {
                     uniforms: vec![buffer],
-                    views: vec![],
                     set,
                 }
             });
@@ -256,9 +264,11 @@ where
     fn draw_inline<'a>(
         &mut self,
         layout: &B::PipelineLayout,
+        _pipelines: &[B::GraphicsPipeline],
         mut encoder: RenderPassInlineEncoder<B, Primary>,
         _device: &B::Device,
         _inputs: &[&B::Image],
+        _samplers: &[&B::Sampler],
         frame: usize,
         scene: &Scene<B, ObjectData>,
     ) {
@@ -280,14 +290,14 @@ where
 
     fn cleanup(
         &mut self,
-        pool: &mut DescriptorPool<B>,
+        pools: &mut [DescriptorPool<B>],
         device: &B::Device,
         scene: &mut Scene<B, ObjectData>,
     ) {
         for object in &mut scene.objects {
             for cache in object.cache.drain(..) {
                 if let Some(cache) = cache {
-                    pool.free(cache.set);
+                    pools[0].free(cache.set);
                     for uniform in cache.uniforms {
                         scene.allocator.destroy_buffer(device, uniform);
                     }
@@ -328,44 +338,31 @@ impl PassDesc for DrawPbmShade {
         false
     }
 
-    fn vertices(&self) -> &[(&[Element<Format>], ElemStride)] {
+    fn vertices(&self) -> &[VertexFormat] {
         &[]
     }
 
-    fn bindings(&self) -> &[DescriptorSetLayoutBinding] {
+    fn bindings(&self) -> &[&[DescriptorSetLayoutBinding]] {
         &[
-            DescriptorSetLayoutBinding {
-                binding: 0,
-                ty: DescriptorType::StorageImage,
-                count: 1,
-                stage_flags: ShaderStageFlags::FRAGMENT,
-            },
-            DescriptorSetLayoutBinding {
-                binding: 1,
-                ty: DescriptorType::StorageImage,
-                count: 1,
-                stage_flags: ShaderStageFlags::FRAGMENT,
-            },
-            DescriptorSetLayoutBinding {
-                binding: 2,
-                ty: DescriptorType::StorageImage,
-                count: 1,
-                stage_flags: ShaderStageFlags::FRAGMENT,
-            },
-            DescriptorSetLayoutBinding {
-                binding: 3,
-                ty: DescriptorType::StorageImage,
-                count: 1,
-                stage_flags: ShaderStageFlags::FRAGMENT,
-            },
-            DescriptorSetLayoutBinding {
-                binding: 4,
-                ty: DescriptorType::UniformBuffer,
-                count: 1,
-                stage_flags: ShaderStageFlags::FRAGMENT,
-            },
+            &[
+                DescriptorSetLayoutBinding {
+                    binding: 0,
+                    ty: DescriptorType::UniformBuffer,
+                    count: 1,
+                    stage_flags: ShaderStageFlags::FRAGMENT,
+                },
+            ],
         ]
     }
+
+    // G-buffer is read by the graph managed set bound after the uniform set
+    fn storage_bindings(&self) -> &[DescriptorBinding] {
+        &[0, 1, 2, 3]
+    }
+
+    fn attachment_stages(&self) -> ShaderStageFlags {
+        ShaderStageFlags::FRAGMENT
+    }
 }
 
 impl<B> PassShaders<B> for DrawPbmShade
@@ -374,12 +371,11 @@ where
 {
     fn shaders<'a>(
         &self,
-        shaders: &'a mut SmallVec<[B::ShaderModule; 5]>,
+        shaders: &'a mut ShaderModules<B>,
         device: &B::Device,
     ) -> Result<GraphicsShaderSet<'a, B>, ShaderError> {
-        shaders.clear();
-        shaders.push(device.create_shader_module(include_bytes!("second.vert.spv"))?);
-        shaders.push(device.create_shader_module(include_bytes!("second.frag.spv"))?);
+        shaders.create(include_bytes!("second.vert.spv"), device)?;
+        shaders.create(include_bytes!("second.frag.spv"), device)?;
 
         Ok(GraphicsShaderSet {
             vertex: EntryPoint {
@@ -405,15 +401,14 @@ where
 {
     fn prepare<'a>(
         &mut self,
-        pool: &mut DescriptorPool<B>,
+        pools: &mut [DescriptorPool<B>],
         cbuf: &mut CommandBuffer<B, Transfer>,
         device: &B::Device,
-        inputs: &[&B::Image],
+        _inputs: &[&B::Image],
+        _samplers: &[&B::Sampler],
         frame: usize,
         scene: &mut Scene<B, ObjectData>,
     ) {
-        assert_eq!(4, inputs.len());
-
         #[derive(Clone, Copy, Debug, PartialEq)]
         struct FragmentArgs {
             light_position: [f32; 3],
@@ -448,51 +443,11 @@ where
                 _pad3: 0.0,
             };
 
-            let color_range = SubresourceRange {
-                aspects: Aspects::COLOR,
-                levels: 0..1,
-                layers: 0..1,
-            };
-
             let size = ::std::mem::size_of::<FragmentArgs>() as u64;
 
             let grow = (light.cache.len()..frame + 1).map(|_| None);
             light.cache.extend(grow);
             let cache = light.cache[frame].get_or_insert_with(|| {
-                let views = vec![
-                    device
-                        .create_image_view(
-                            inputs[0],
-                            Format::Rgba32Float,
-                            Swizzle::NO,
-                            color_range.clone(),
-                        )
-                        .unwrap(),
-                    device
-                        .create_image_view(
-                            inputs[1],
-                            Format::Rgba32Float,
-                            Swizzle::NO,
-                            color_range.clone(),
-                        )
-                        .unwrap(),
-                    device
-                        .create_image_view(
-                            inputs[2],
-                            Format::Rgba32Float,
-                            Swizzle::NO,
-                            color_range.clone(),
-                        )
-                        .unwrap(),
-                    device
-                        .create_image_view(
-                            inputs[3],
-                            Format::Rgba32Float,
-                            Swizzle::NO,
-                            color_range.clone(),
-                        )
-                        .unwrap(),
-                ];
                 let buffer = allocator
                     .create_buffer(
                         device,
@@ -501,75 +456,21 @@ where
                         Usage::UNIFORM | Usage::TRANSFER_DST,
                     )
                     .unwrap();
-                let set = pool.allocate(device);
+                let set = pools[0].allocate(device);
                 device.write_descriptor_sets(&[
                     DescriptorSetWrite {
                         set: &set,
                         binding: 0,
                         array_offset: 0,
-                        write: DescriptorWrite::StorageImage(&[(&views[0], ImageLayout::General)]),
-                    },
-                    DescriptorSetWrite {
-                        set: &set,
-                        binding: 1,
-                        array_offset: 0,
-                        write: DescriptorWrite::StorageImage(&[(&views[1], ImageLayout::General)]),
-                    },
-                    DescriptorSetWrite {
-                        set: &set,
-                        binding: 2,
-                        array_offset: 0,
-                        write: DescriptorWrite::StorageImage(&[(&views[2], ImageLayout::General)]),
-                    },
-                    DescriptorSetWrite {
-                        set: &set,
-                        binding: 3,
-                        array_offset: 0,
-                        write: DescriptorWrite::StorageImage(&[(&views[3], ImageLayout::General)]),
-                    },
-                    DescriptorSetWrite {
-                        set: &set,
-                        binding: 4,
-                        array_offset: 0,
                         write: DescriptorWrite::UniformBuffer(&[(buffer.borrow(), 0..size)]),
                     },
                 ]);
                 Cache {
                     uniforms: vec![buffer],
-                    views,
                     set,
                 }
             });
 
-            let states = (Access::COLOR_ATTACHMENT_WRITE, ImageLayout::General)
-                ..(Access::SHADER_READ, ImageLayout::General);
-            cbuf.pipeline_barrier(
-                PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::FRAGMENT_SHADER,
-                Dependencies::empty(),
-                &[
-                    Barrier::Image {
-                        states: states.clone(),
-                        target: inputs[0],
-                        range: color_range.clone(),
-                    },
-                    Barrier::Image {
-                        states: states.clone(),
-                        target: inputs[1],
-                        range: color_range.clone(),
-                    },
-                    Barrier::Image {
-                        states: states.clone(),
-                        target: inputs[2],
-                        range: color_range.clone(),
-                    },
-                    Barrier::Image {
-                        states: states.clone(),
-                        target: inputs[3],
-                        range: color_range.clone(),
-                    },
-                ],
-            );
-
             cbuf.update_buffer(cache.uniforms[0].borrow(), 0, cast_slice(&[fragment_args]));
         }
     }
@@ -577,9 +478,11 @@ where
     fn draw_inline<'a>(
         &mut self,
         layout: &B::PipelineLayout,
+        _pipelines: &[B::GraphicsPipeline],
         mut encoder: RenderPassInlineEncoder<B, Primary>,
         _device: &B::Device,
         _inputs: &[&B::Image],
+        _samplers: &[&B::Sampler],
         frame: usize,
         scene: &Scene<B, ObjectData>,
     ) {
@@ -595,14 +498,14 @@ where
 
     fn cleanup(
         &mut self,
-        pool: &mut DescriptorPool<B>,
+        pools: &mut [DescriptorPool<B>],
         device: &B::Device,
         scene: &mut Scene<B, ObjectData>,
     ) {
         for object in &mut scene.objects {
             for cache in object.cache.drain(..) {
                 if let Some(cache) = cache {
-                    pool.free(cache.set);
+                    pools[0].free(cache.set);
                     for uniform in cache.uniforms {
                         scene.allocator.destroy_buffer(device, uniform);
                     }
@@ -613,7 +516,7 @@ where
         for light in &mut scene.lights {
             for cache in light.cache.drain(..) {
                 if let Some(cache) = cache {
-                    pool.free(cache.set);
+                    pools[0].free(cache.set);
                     for uniform in cache.uniforms {
                         scene.allocator.destroy_buffer(device, uniform);
                     }
@@ -665,7 +568,9 @@ fn graph<'a>(surface_format: Format, graph: &mut GraphBuilder<AnyPass>) {
         .with_storage(position_depth)
         .with_color_blend(present, ColorBlendDesc(ColorMask::ALL, BlendState::ADD));
 
-    graph.add_pass(prepare).add_pass(shade).set_present(present);
+    graph.add_pass(prepare);
+    graph.add_pass(shade);
+    graph.set_present(present);
 }
 
 fn fill<B>(scene: &mut Scene<B, ObjectData>, device: &B::Device)
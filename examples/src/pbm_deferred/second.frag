@@ -1,12 +1,12 @@
 #version 450 core
 #extension GL_ARB_separate_shader_objects : enable
 
-layout(set = 0, binding = 0, rgba32f) uniform readonly image2D input_albedo_roughness;
-layout(set = 0, binding = 1, rgba32f) uniform readonly image2D input_emission_metallic;
-layout(set = 0, binding = 2, rgba32f) uniform readonly image2D input_normal_ambient_occlusion;
-layout(set = 0, binding = 3, rgba32f) uniform readonly image2D input_position;
+layout(set = 1, binding = 0, rgba32f) uniform readonly image2D input_albedo_roughness;
+layout(set = 1, binding = 1, rgba32f) uniform readonly image2D input_emission_metallic;
+layout(set = 1, binding = 2, rgba32f) uniform readonly image2D input_normal_ambient_occlusion;
+layout(set = 1, binding = 3, rgba32f) uniform readonly image2D input_position;
 
-layout(set = 0, binding = 4) uniform FragmentArgs {
+layout(set = 0, binding = 0) uniform FragmentArgs {
     vec3 light_position;
     float _pad0;
     vec3 light_color;
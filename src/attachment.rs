@@ -5,7 +5,7 @@ use std::ops::Range;
 
 use gfx_hal::command::{ClearColor, ClearDepthStencil, ClearValue};
 use gfx_hal::format::Format;
-use gfx_hal::image::{ImageLayout, Usage as ImageUsage};
+use gfx_hal::image::{Access, ImageLayout, State, Usage as ImageUsage};
 use gfx_hal::pass::{AttachmentLoadOp, AttachmentStoreOp};
 
 /// Attachment declaration.
@@ -13,6 +13,7 @@ use gfx_hal::pass::{AttachmentLoadOp, AttachmentStoreOp};
 pub struct Attachment {
     pub(crate) format: Format,
     pub(crate) clear: Option<ClearValue>,
+    pub(crate) name: Option<&'static str>,
 }
 
 impl From<ColorAttachment> for Attachment {
@@ -42,6 +43,7 @@ impl ColorAttachment {
         ColorAttachment(Attachment {
             format,
             clear: None,
+            name: None,
         })
     }
 
@@ -57,6 +59,19 @@ impl ColorAttachment {
     pub fn set_clear(&mut self, clear: ClearColor) {
         self.0.clear = Some(ClearValue::Color(clear));
     }
+
+    /// Set name of the attachment.
+    /// Images and views created for the attachment are named after it in graphics debuggers.
+    pub fn with_name(mut self, name: &'static str) -> Self {
+        self.set_name(name);
+        self
+    }
+
+    /// Set name of the attachment.
+    /// Images and views created for the attachment are named after it in graphics debuggers.
+    pub fn set_name(&mut self, name: &'static str) {
+        self.0.name = Some(name);
+    }
 }
 
 /// Attachment declaration with depth-stencil format.
@@ -68,12 +83,13 @@ impl DepthStencilAttachment {
     ///
     /// # Panics
     ///
-    /// If format aspect doesn't contain depth.
+    /// If format aspect contains neither depth nor stencil.
     pub fn new(format: Format) -> Self {
-        assert!(format.is_depth());
+        assert!(format.is_depth() || format.is_stencil());
         DepthStencilAttachment(Attachment {
             format,
             clear: None,
+            name: None,
         })
     }
 
@@ -89,6 +105,19 @@ impl DepthStencilAttachment {
     pub fn set_clear(&mut self, clear: ClearDepthStencil) {
         self.0.clear = Some(ClearValue::DepthStencil(clear));
     }
+
+    /// Set name of the attachment.
+    /// Images and views created for the attachment are named after it in graphics debuggers.
+    pub fn with_name(mut self, name: &'static str) -> Self {
+        self.set_name(name);
+        self
+    }
+
+    /// Set name of the attachment.
+    /// Images and views created for the attachment are named after it in graphics debuggers.
+    pub fn set_name(&mut self, name: &'static str) {
+        self.0.name = Some(name);
+    }
 }
 
 /// Reference to either color or depth-stencil attachment declaration in `GraphBuilder`.
@@ -100,16 +129,63 @@ impl AttachmentRef {
     }
 }
 
+/// Kind of access a pass performs on an attachment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum AttachmentUsage {
+    Input,
+    Color,
+    DepthStencil,
+    DepthStencilReadOnly,
+    Sampled,
+    Storage,
+    StorageWrite,
+}
+
+impl AttachmentUsage {
+    /// Check if attachment is used by the renderpass of the pass.
+    /// Renderpass transitions layouts of its attachments. Other attachments must be in
+    /// required layout before the pass starts.
+    fn is_renderpass(&self) -> bool {
+        match *self {
+            AttachmentUsage::Input
+            | AttachmentUsage::Color
+            | AttachmentUsage::DepthStencil
+            | AttachmentUsage::DepthStencilReadOnly => true,
+            AttachmentUsage::Sampled | AttachmentUsage::Storage | AttachmentUsage::StorageWrite => {
+                false
+            }
+        }
+    }
+
+    /// Memory access performed with the usage.
+    fn access(&self) -> Access {
+        match *self {
+            AttachmentUsage::Input => Access::INPUT_ATTACHMENT_READ,
+            AttachmentUsage::Color => {
+                Access::COLOR_ATTACHMENT_READ | Access::COLOR_ATTACHMENT_WRITE
+            }
+            AttachmentUsage::DepthStencil => {
+                Access::DEPTH_STENCIL_ATTACHMENT_READ | Access::DEPTH_STENCIL_ATTACHMENT_WRITE
+            }
+            AttachmentUsage::DepthStencilReadOnly => Access::DEPTH_STENCIL_ATTACHMENT_READ,
+            AttachmentUsage::Sampled | AttachmentUsage::Storage => Access::SHADER_READ,
+            AttachmentUsage::StorageWrite => Access::SHADER_READ | Access::SHADER_WRITE,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct AttachmentDesc {
     pub(crate) format: Format,
     pub(crate) clear: Option<ClearValue>,
+    pub(crate) name: Option<&'static str>,
     pub(crate) write: Option<Range<usize>>,
     pub(crate) read: Option<Range<usize>>,
     pub(crate) images: Option<Range<usize>>,
     pub(crate) views: Option<Range<usize>>,
     pub(crate) is_surface: bool,
     pub(crate) usage: ImageUsage,
+    pub(crate) uses: Vec<(usize, AttachmentUsage)>,
 }
 
 impl AttachmentDesc {
@@ -143,7 +219,7 @@ impl AttachmentDesc {
 
     pub(crate) fn store_op(&self, index: usize) -> AttachmentStoreOp {
         if self.is_last_touch(index) && !self.is_surface {
-            if self.is_last_write(index) && !self.format.is_depth() {
+            if self.is_last_write(index) && self.format.is_color() {
                 warn!(
                     "Pass at index {} writes to an attachment and nobody reads it",
                     index
@@ -155,17 +231,111 @@ impl AttachmentDesc {
         }
     }
 
+    pub(crate) fn stencil_load_op(&self, index: usize) -> AttachmentLoadOp {
+        if self.format.is_stencil() {
+            self.load_op(index)
+        } else {
+            AttachmentLoadOp::DontCare
+        }
+    }
+
+    pub(crate) fn stencil_store_op(&self, index: usize) -> AttachmentStoreOp {
+        if self.format.is_stencil() && !(self.is_last_touch(index) && !self.is_surface) {
+            AttachmentStoreOp::Store
+        } else {
+            AttachmentStoreOp::DontCare
+        }
+    }
+
+    /// Layout of the attachment when it is accessed from shaders.
+    /// Storage images must be in `General` layout, so attachments used as storage
+    /// are sampled in `General` layout as well. This way no transitions are required
+    /// between passes that access the attachment outside of renderpass.
+    pub(crate) fn shader_layout(&self) -> ImageLayout {
+        if self.usage.contains(ImageUsage::STORAGE) {
+            ImageLayout::General
+        } else if self.format.is_color() {
+            ImageLayout::ShaderReadOnlyOptimal
+        } else {
+            ImageLayout::DepthStencilReadOnlyOptimal
+        }
+    }
+
+    /// Layout required for the usage.
+    fn layout(&self, usage: AttachmentUsage) -> ImageLayout {
+        match usage {
+            AttachmentUsage::Input if self.format.is_color() => {
+                ImageLayout::ShaderReadOnlyOptimal
+            }
+            AttachmentUsage::Input => ImageLayout::DepthStencilReadOnlyOptimal,
+            AttachmentUsage::Color => ImageLayout::ColorAttachmentOptimal,
+            AttachmentUsage::DepthStencil => ImageLayout::DepthStencilAttachmentOptimal,
+            AttachmentUsage::DepthStencilReadOnly => ImageLayout::DepthStencilReadOnlyOptimal,
+            AttachmentUsage::Sampled
+            | AttachmentUsage::Storage
+            | AttachmentUsage::StorageWrite => self.shader_layout(),
+        }
+    }
+
+    /// Layout the attachment is left in after use at `position` in `uses`.
+    /// If next pass accesses the attachment outside of renderpass
+    /// the attachment is transitioned to the layout it requires.
+    /// Otherwise next renderpass performs the transition.
+    fn layout_after(&self, position: usize) -> ImageLayout {
+        match self.uses.get(position + 1) {
+            Some(&(_, next)) if !next.is_renderpass() => self.layout(next),
+            Some(_) => self.layout(self.uses[position].1),
+            None if self.is_surface => ImageLayout::Present,
+            None => self.layout(self.uses[position].1),
+        }
+    }
+
+    /// Initial and final layouts of the attachment in the renderpass of the pass.
+    /// Pass can use the attachment more than once.
+    /// E.g. sample depth-stencil attachment it tests against in read-only layout.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `index`: index of the pass that uses the attachment
     pub(crate) fn image_layout_transition(&self, index: usize) -> Range<ImageLayout> {
-        let start = if self.is_first_touch(index) {
+        let first = self.uses
+            .iter()
+            .position(|&(pass, _)| pass == index)
+            .expect("Attachment is used by the pass");
+        let last = self.uses
+            .iter()
+            .rposition(|&(pass, _)| pass == index)
+            .expect("Attachment is used by the pass");
+        let start = if first == 0 {
             ImageLayout::Undefined
         } else {
-            ImageLayout::General
+            self.layout_after(first - 1)
         };
-        let end = if self.is_last_touch(index) && self.is_surface {
-            ImageLayout::Present
+        start..self.layout_after(last)
+    }
+
+    /// States of the storage attachment before and after the barrier recorded by the pass.
+    /// Storage attachments aren't transitioned by renderpass.
+    /// So the first pass that uses the attachment transitions it from `Undefined` layout.
+    /// Following passes wait for previous accesses to complete.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `index`: index of the pass that uses the attachment as storage
+    pub(crate) fn storage_barrier_states(&self, index: usize) -> Range<State> {
+        let position = self.uses
+            .iter()
+            .position(|&(pass, usage)| {
+                pass == index
+                    && (usage == AttachmentUsage::Storage || usage == AttachmentUsage::StorageWrite)
+            })
+            .expect("Attachment is used by the pass as storage");
+        let usage = self.uses[position].1;
+        let start = if position == 0 {
+            (Access::empty(), ImageLayout::Undefined)
         } else {
-            ImageLayout::General
+            (self.uses[position - 1].1.access(), self.layout_after(position - 1))
         };
-        start..end
+        start..(usage.access(), self.layout(usage))
     }
 }
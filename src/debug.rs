@@ -0,0 +1,67 @@
+//! Debug annotations for graphics debuggers.
+//!
+//! `gfx_hal` doesn't expose debug markers, so the `Graph` reports names of its objects
+//! through the `DebugMarkers` trait. Applications can implement it on top of
+//! backend specific extensions. `NoDebugMarkers` is used by default.
+//!
+
+use std::fmt::Debug;
+
+use gfx_hal::Backend;
+use gfx_hal::command::CommandBuffer;
+use gfx_hal::queue::capability::Transfer;
+
+/// Receiver of debug names of the `Graph` objects.
+/// All methods do nothing by default.
+///
+/// ### Type parameters:
+///
+/// - `B`: render `Backend`
+pub trait DebugMarkers<B>: Debug + Send + Sync
+where
+    B: Backend,
+{
+    /// Begin named group of commands.
+    /// The `Graph` opens group named after `PassDesc::name` before commands of each pass.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `cbuf`: command buffer commands are recorded to
+    /// - `name`: name of the group
+    fn begin_group(&self, _cbuf: &mut CommandBuffer<B, Transfer>, _name: &str) {}
+
+    /// End group of commands opened last with `DebugMarkers::begin_group`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `cbuf`: command buffer commands are recorded to
+    fn end_group(&self, _cbuf: &mut CommandBuffer<B, Transfer>) {}
+
+    /// Name an image created for an attachment.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: graphics device
+    /// - `image`: image to name
+    /// - `name`: name of the image
+    fn name_image(&self, _device: &B::Device, _image: &B::Image, _name: &str) {}
+
+    /// Name a view created for an attachment.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: graphics device
+    /// - `view`: image view to name
+    /// - `name`: name of the view
+    fn name_view(&self, _device: &B::Device, _view: &B::ImageView, _name: &str) {}
+}
+
+/// `DebugMarkers` implementation that does nothing.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoDebugMarkers;
+
+impl<B> DebugMarkers<B> for NoDebugMarkers
+where
+    B: Backend,
+{
+}
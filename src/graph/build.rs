@@ -6,14 +6,16 @@ use std::ops::Range;
 use gfx_hal::{Backend, Device};
 use gfx_hal::device::{Extent, FramebufferError, ShaderError};
 use gfx_hal::format::{Format, Swizzle};
-use gfx_hal::image::{AaMode, Kind, Level, SubresourceRange, Usage as ImageUsage};
+use gfx_hal::image::{AaMode, Kind, Level, SamplerInfo, SubresourceRange, Usage as ImageUsage};
 use gfx_hal::memory::Properties;
-use gfx_hal::pso::{CreationError, PipelineStage};
+use gfx_hal::pso::{CreationError, Location, PipelineStage};
 use gfx_hal::window::Backbuffer;
 
-use attachment::{Attachment, AttachmentDesc, AttachmentRef};
+use attachment::{Attachment, AttachmentDesc, AttachmentRef, AttachmentUsage};
+use debug::{DebugMarkers, NoDebugMarkers};
 use graph::Graph;
-use pass::{PassBuilder, PassNode, PassShaders};
+use graph::timestamps::Timestamps;
+use pass::{create_graphics_pipelines, PassBuilder, PassNode, PassRef, PassShaders, SharedObjects};
 
 /// Possible errors during graph building
 #[derive(Debug, Clone)]
@@ -30,6 +32,8 @@ pub enum GraphBuildError<E> {
     AllocationError(E),
     /// Graph configuration is invalid.
     InvalidConfiguaration,
+    /// Several vertex attributes of a pass are placed at the same shader location
+    VertexAttributeOverlap(Location),
     /// Any other errors encountered during graph building
     Other,
 }
@@ -75,6 +79,11 @@ where
             GraphBuildError::InvalidConfiguaration => {
                 write!(fmt, "Graph has invalid configuration")
             }
+            GraphBuildError::VertexAttributeOverlap(location) => write!(
+                fmt,
+                "Several vertex attributes are placed at location {}",
+                location
+            ),
             GraphBuildError::Other => fmt.write_str("Unknown error has occured"),
         }
     }
@@ -108,6 +117,8 @@ pub struct GraphBuilder<P> {
     passes: Vec<PassBuilder<P>>,
     present: Option<AttachmentRef>,
     extent: Extent,
+    timestamp_period: Option<f32>,
+    parallel_pipelines: bool,
 }
 
 impl<P> GraphBuilder<P> {
@@ -122,6 +133,8 @@ impl<P> GraphBuilder<P> {
                 height: 0,
                 depth: 0,
             },
+            timestamp_period: None,
+            parallel_pipelines: false,
         }
     }
 
@@ -166,14 +179,16 @@ impl<P> GraphBuilder<P> {
         self
     }
 
-    /// Add a `Pass` to the `Graph`
+    /// Add a `Pass` to the `Graph` and return a value to reference added pass.
+    /// Other passes can depend on it with `PassBuilder::with_dependency`.
     ///
     /// ### Parameters:
     ///
     /// - `pass`: pass builder
-    pub fn add_pass(&mut self, pass: PassBuilder<P>) -> &mut Self {
+    pub fn add_pass(&mut self, mut pass: PassBuilder<P>) -> PassRef {
+        pass.index = self.passes.len();
         self.passes.push(pass);
-        self
+        PassRef(self.passes.len() - 1)
     }
 
     /// Set the extent of the framebuffers
@@ -216,6 +231,54 @@ impl<P> GraphBuilder<P> {
         self
     }
 
+    /// Enable GPU profiling of the passes.
+    /// Timestamps will be written around render pass of each pass
+    /// and can be read with `Graph::pass_timings`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `timestamp_period`: number of nanoseconds per timestamp tick,
+    ///                       see `Limits::timestamp_period`
+    pub fn with_profiling(mut self, timestamp_period: f32) -> Self {
+        self.set_profiling(timestamp_period);
+        self
+    }
+
+    /// Enable GPU profiling of the passes.
+    /// Timestamps will be written around render pass of each pass
+    /// and can be read with `Graph::pass_timings`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `timestamp_period`: number of nanoseconds per timestamp tick,
+    ///                       see `Limits::timestamp_period`
+    pub fn set_profiling(&mut self, timestamp_period: f32) -> &mut Self {
+        self.timestamp_period = Some(timestamp_period);
+        self
+    }
+
+    /// Split creation of graphics pipelines between worker threads.
+    /// Pipelines of all passes are created with a single call by default.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `parallel`: create pipelines on worker threads
+    pub fn with_parallel_pipelines(mut self, parallel: bool) -> Self {
+        self.set_parallel_pipelines(parallel);
+        self
+    }
+
+    /// Split creation of graphics pipelines between worker threads.
+    /// Pipelines of all passes are created with a single call by default.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `parallel`: create pipelines on worker threads
+    pub fn set_parallel_pipelines(&mut self, parallel: bool) -> &mut Self {
+        self.parallel_pipelines = parallel;
+        self
+    }
+
     /// Build rendering graph
     ///
     /// ### Parameters:
@@ -229,20 +292,61 @@ impl<P> GraphBuilder<P> {
     /// - `I`: render target image type
     /// - `E`: errors returned by the allocator function
     pub fn build<B, A, I, E>(
+        self,
+        device: &B::Device,
+        backbuffer: &Backbuffer<B>,
+        allocator: A,
+    ) -> Result<Graph<B, I, P>, GraphBuildError<E>>
+    where
+        B: Backend,
+        A: FnMut(Kind, Level, Format, ImageUsage, Properties, &B::Device) -> Result<I, E>,
+        I: Borrow<B::Image>,
+        P: PassShaders<B>,
+    {
+        self.build_with_markers(device, backbuffer, allocator, NoDebugMarkers)
+    }
+
+    /// Build rendering graph that reports names of its objects to `markers`.
+    /// Images and views are named after attachments
+    /// and commands of each pass are grouped under the pass name.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: graphics device
+    /// - `allocator`: allocator function used for creating render targets
+    /// - `markers`: receiver of debug names
+    ///
+    /// ### Type parameters:
+    ///
+    /// - `A`: allocator function
+    /// - `I`: render target image type
+    /// - `E`: errors returned by the allocator function
+    /// - `M`: debug markers implementation
+    pub fn build_with_markers<B, A, I, E, M>(
         self,
         device: &B::Device,
         backbuffer: &Backbuffer<B>,
         mut allocator: A,
+        markers: M,
     ) -> Result<Graph<B, I, P>, GraphBuildError<E>>
     where
         B: Backend,
         A: FnMut(Kind, Level, Format, ImageUsage, Properties, &B::Device) -> Result<I, E>,
         I: Borrow<B::Image>,
         P: PassShaders<B>,
+        M: DebugMarkers<B> + 'static,
     {
         info!("Building graph from {:?}", self);
         let present = self.present
             .ok_or(GraphBuildError::PresentationAttachmentNotSet)?;
+        // Passes can depend only on passes added before them
+        if self.passes
+            .iter()
+            .any(|pass| pass.dependencies.iter().any(|dep| dep.index() >= pass.index))
+        {
+            error!("Pass depends on itself or a pass added after it");
+            return Err(GraphBuildError::InvalidConfiguaration);
+        }
 
         info!("Collect views from backbuffer");
         // Create views for backbuffer
@@ -274,12 +378,14 @@ impl<P> GraphBuilder<P> {
             .map(|a| AttachmentDesc {
                 format: a.format,
                 clear: a.clear,
+                name: a.name,
                 write: None,
                 read: None,
                 images: None,
                 views: None,
                 is_surface: false,
                 usage: ImageUsage::empty(),
+                uses: Vec::new(),
             })
             .collect::<Vec<_>>();
 
@@ -289,7 +395,8 @@ impl<P> GraphBuilder<P> {
         info!("Reorder passes to maximize overlapping");
         // Reorder passes to maximise overlapping
         // while keeping all dependencies before dependants.
-        let (passes, deps) = reorder_passes(self.passes);
+        let (passes, deps) =
+            reorder_passes(self.passes).ok_or(GraphBuildError::InvalidConfiguaration)?;
 
         info!("Reordered passes {:#?}", passes);
         info!("Dependencies {:#?}", deps);
@@ -300,7 +407,7 @@ impl<P> GraphBuilder<P> {
         let mut pass_nodes: Vec<PassNode<B, P>> = Vec::new();
         for (pass_index, pass) in passes.iter().enumerate() {
             info!("Check sampled targets");
-            for &sampled in &pass.sampled {
+            for &(sampled, _) in &pass.sampled {
                 let ref mut sampled = attachments[sampled.0];
                 debug_assert!(sampled.write.is_some());
                 sampled
@@ -308,16 +415,28 @@ impl<P> GraphBuilder<P> {
                     .get_or_insert_with(|| pass_index..pass_index)
                     .end = pass_index;
                 sampled.usage |= ImageUsage::SAMPLED;
+                sampled.uses.push((pass_index, AttachmentUsage::Sampled));
             }
 
             info!("Check storage targets");
-            for &storage in &pass.storages {
+            for &(storage, written) in &pass.storages {
                 let ref mut storage = attachments[storage.0];
-                debug_assert!(storage.write.is_some());
-                storage
-                    .read
-                    .get_or_insert_with(|| pass_index..pass_index)
-                    .end = pass_index;
+                if written {
+                    storage
+                        .write
+                        .get_or_insert_with(|| pass_index..pass_index)
+                        .end = pass_index;
+                    storage
+                        .uses
+                        .push((pass_index, AttachmentUsage::StorageWrite));
+                } else {
+                    debug_assert!(storage.write.is_some());
+                    storage
+                        .read
+                        .get_or_insert_with(|| pass_index..pass_index)
+                        .end = pass_index;
+                    storage.uses.push((pass_index, AttachmentUsage::Storage));
+                }
                 storage.usage |= ImageUsage::STORAGE;
             }
 
@@ -326,6 +445,7 @@ impl<P> GraphBuilder<P> {
                 let ref mut input = attachments[input.0];
                 debug_assert!(input.write.is_some());
                 input.read.get_or_insert_with(|| pass_index..pass_index).end = pass_index;
+                input.uses.push((pass_index, AttachmentUsage::Input));
                 // input.usage |= ImageUsage::INPUT_ATTACHMENT;
                 unimplemented!()
             }
@@ -338,30 +458,67 @@ impl<P> GraphBuilder<P> {
                     .get_or_insert_with(|| pass_index..pass_index)
                     .end = pass_index;
                 color.usage |= ImageUsage::COLOR_ATTACHMENT;
+                color.uses.push((pass_index, AttachmentUsage::Color));
             }
 
             info!("Check depth-stencil target");
             if let Some(depth_stencil) = pass.depth_stencil {
                 let ref mut depth_stencil = attachments[depth_stencil.0.index()];
-                depth_stencil
-                    .write
-                    .get_or_insert_with(|| pass_index..pass_index)
-                    .end = pass_index;
+                if pass.depth_read_only {
+                    debug_assert!(depth_stencil.write.is_some());
+                    depth_stencil
+                        .read
+                        .get_or_insert_with(|| pass_index..pass_index)
+                        .end = pass_index;
+                    depth_stencil
+                        .uses
+                        .push((pass_index, AttachmentUsage::DepthStencilReadOnly));
+                } else {
+                    depth_stencil
+                        .write
+                        .get_or_insert_with(|| pass_index..pass_index)
+                        .end = pass_index;
+                    depth_stencil
+                        .uses
+                        .push((pass_index, AttachmentUsage::DepthStencil));
+                }
                 depth_stencil.usage |= ImageUsage::DEPTH_STENCIL_ATTACHMENT;
             }
+
+            info!("Check forwarded targets");
+            if let Some((from, to)) = pass.forward {
+                attachments[from.index()].usage |= ImageUsage::TRANSFER_SRC;
+                attachments[to.index()].usage |= ImageUsage::TRANSFER_DST;
+            }
         }
 
         for pass in passes.iter() {
             info!("Ensure sampled targets are created");
-            for &sampled in &pass.sampled {
+            for &(sampled, _) in &pass.sampled {
                 assert!(attachments[sampled.0].views.is_some());
                 assert!(attachments[sampled.0].images.is_some());
             }
 
-            info!("Ensure storage targets are created");
-            for &storage in &pass.storages {
-                assert!(attachments[storage.0].views.is_some());
-                assert!(attachments[storage.0].images.is_some());
+            info!("Create written storage targets");
+            for &(storage, written) in &pass.storages {
+                let ref mut storage = attachments[storage.0];
+                if storage.views.is_none() {
+                    assert!(written);
+                    debug_assert!(storage.images.is_none());
+                    create_target::<B, _, I, E>(
+                        storage.format,
+                        storage.usage,
+                        &mut allocator,
+                        device,
+                        &mut images,
+                        &mut image_views,
+                        self.extent,
+                        frames,
+                    ).map_err(GraphBuildError::AllocationError)?;
+                    storage.views = Some((image_views.len() - frames..image_views.len()));
+                    storage.images = Some((images.len() - frames..images.len()));
+                }
+                assert!(storage.images.is_some());
             }
 
             info!("Ensure sampled targets are created");
@@ -411,9 +568,46 @@ impl<P> GraphBuilder<P> {
             }
         }
 
+        info!("Name attachments");
+        for (index, attachment) in attachments.iter().enumerate() {
+            let name = attachment
+                .name
+                .map_or_else(|| format!("Attachment {}", index), str::to_string);
+            if let Some(ref range) = attachment.images {
+                for (frame, image) in images[range.clone()].iter().enumerate() {
+                    markers.name_image(device, image.borrow(), &format!("{} #{}", name, frame));
+                }
+            }
+            if let Some(ref range) = attachment.views {
+                for (frame, view) in image_views[range.clone()].iter().enumerate() {
+                    markers.name_view(device, view, &format!("{} #{}", name, frame));
+                }
+            }
+        }
+
+        info!("Create samplers");
+        // Passes that sample with same description share a sampler.
+        let mut samplers: Vec<(SamplerInfo, B::Sampler)> = Vec::new();
+        for pass in passes.iter() {
+            for &(_, ref info) in &pass.sampled {
+                if samplers.iter().all(|&(ref created, _)| created != info) {
+                    samplers.push((info.clone(), device.create_sampler(info.clone())));
+                }
+            }
+        }
+
         info!("Build pass nodes from pass builders");
+        let mut shared = SharedObjects::new();
         for ((pass_index, pass), last_dep) in passes.into_iter().enumerate().zip(deps) {
-            let mut node = pass.build(device, self.extent, &attachments, &image_views, pass_index)?;
+            let mut node = pass.build(
+                device,
+                self.extent,
+                &attachments,
+                &image_views,
+                &samplers,
+                &mut shared,
+                pass_index,
+            )?;
 
             if let Some(last_dep) = last_dep {
                 node.depends = if pass_nodes
@@ -436,6 +630,31 @@ impl<P> GraphBuilder<P> {
             pass_nodes.push(node);
         }
 
+        info!("Create graphics pipelines");
+        let pipelines = create_graphics_pipelines(
+            &pass_nodes.iter().collect::<Vec<_>>(),
+            self.parallel_pipelines,
+            device,
+        );
+        let mut created = Vec::new();
+        let mut error = None;
+        for pipelines in pipelines {
+            match pipelines {
+                Ok(pipelines) => created.push(pipelines),
+                Err(err) => error = error.or(Some(err)),
+            }
+        }
+        if let Some(error) = error {
+            // Don't leak pipelines of the passes that succeeded
+            for pipeline in created.into_iter().flatten() {
+                device.destroy_graphics_pipeline(pipeline);
+            }
+            return Err(error.into());
+        }
+        for (node, pipelines) in pass_nodes.iter_mut().zip(created) {
+            node.set_graphics_pipelines(pipelines, device);
+        }
+
         info!("Create semaphores");
         let mut signals = Vec::new();
         for i in 0..pass_nodes.len() {
@@ -462,20 +681,30 @@ impl<P> GraphBuilder<P> {
             }
         }
 
+        let timestamps = self.timestamp_period.map(|period| {
+            info!("Create timestamp query pool");
+            Timestamps::new(device, pass_nodes.len(), frames, period)
+        });
+
         Ok(Graph {
             passes: pass_nodes,
             signals,
             images,
             views: image_views,
+            samplers: samplers.into_iter().map(|(_, sampler)| sampler).collect(),
+            timestamps,
+            markers: Box::new(markers),
             frames,
             draws_to_surface: attachments[present.0].write.clone().unwrap(),
+            parallel_pipelines: self.parallel_pipelines,
+            clears: attachments.iter().map(|attachment| attachment.clear).collect(),
         })
     }
 }
 
 fn reorder_passes<P>(
     mut unscheduled: Vec<PassBuilder<P>>,
-) -> (Vec<PassBuilder<P>>, Vec<Option<usize>>) {
+) -> Option<(Vec<PassBuilder<P>>, Vec<Option<usize>>)> {
     // Ordered passes
     let mut scheduled = vec![];
     let mut deps = vec![];
@@ -483,7 +712,7 @@ fn reorder_passes<P>(
     // Until we schedule all unscheduled passes
     while !unscheduled.is_empty() {
         // Walk over unscheduled
-        let (last_dep, index) = (0..unscheduled.len())
+        let next = (0..unscheduled.len())
             .filter(|&index| {
                 // Check if all dependencies are scheduled
                 dependencies(&unscheduled, &unscheduled[index]).is_empty()
@@ -494,16 +723,23 @@ fn reorder_passes<P>(
                 (dependencies.into_iter().chain(siblings).max(), index)
             })
             // Smallest index of last dependency wins. `None < Some(0)`
-            .min_by_key(|&(last_dep, _)| last_dep)
-            // At least one pass with all dependencies scheduled must be found.
-            // Or there is dependency circle in unscheduled left.
-            .expect("Circular dependency encountered");
+            .min_by_key(|&(last_dep, _)| last_dep);
+
+        // At least one pass with all dependencies scheduled must be found.
+        // Or there is dependency circle in unscheduled left.
+        let (last_dep, index) = match next {
+            Some(next) => next,
+            None => {
+                error!("Circular dependency encountered");
+                return None;
+            }
+        };
 
         // Store
         scheduled.push(unscheduled.swap_remove(index));
         deps.push(last_dep);
     }
-    (scheduled, deps)
+    Some((scheduled, deps))
 }
 
 /// Get dependencies of pass.
@@ -511,20 +747,31 @@ fn direct_dependencies<P>(passes: &[PassBuilder<P>], pass: &PassBuilder<P>) -> V
     let mut deps = Vec::new();
     for &input in pass.sampled
         .iter()
-        .chain(&pass.storages)
+        .map(|&(ref a, _)| a)
+        .chain(
+            pass.storages
+                .iter()
+                .filter(|&&(_, written)| !written)
+                .map(|&(ref a, _)| a),
+        )
         .chain(&pass.inputs)
+        .chain(read_only_depth(pass))
     {
         deps.extend(
             passes
                 .iter()
                 .enumerate()
-                .filter(|p| {
-                    p.1.depth_stencil.map(|(a, _)| a) == Some(input)
-                        || p.1.colors.iter().any(|&(a, _)| input == a)
-                })
+                .filter(|p| outputs(p.1).contains(&input))
                 .map(|p| p.0),
         );
     }
+    deps.extend(
+        passes
+            .iter()
+            .enumerate()
+            .filter(|p| pass.dependencies.iter().any(|dep| dep.index() == p.1.index))
+            .map(|p| p.0),
+    );
     deps.sort();
     deps.dedup();
     deps
@@ -533,26 +780,12 @@ fn direct_dependencies<P>(passes: &[PassBuilder<P>], pass: &PassBuilder<P>) -> V
 /// Get other passes that shares output attachments
 fn siblings<P>(passes: &[PassBuilder<P>], pass: &PassBuilder<P>) -> Vec<usize> {
     let mut siblings = Vec::new();
-    for &color in pass.colors.iter() {
-        siblings.extend(
-            passes
-                .iter()
-                .enumerate()
-                .filter(|p| p.1.colors.iter().any(|&a| a == color))
-                .map(|p| p.0),
-        );
-    }
-    if let Some(depth_stencil) = pass.depth_stencil {
+    for output in outputs(pass) {
         siblings.extend(
             passes
                 .iter()
                 .enumerate()
-                .filter(|p| {
-                    p.1
-                        .depth_stencil
-                        .as_ref()
-                        .map_or(false, |&a| a == depth_stencil)
-                })
+                .filter(|p| outputs(p.1).contains(&output))
                 .map(|p| p.0),
         );
     }
@@ -561,14 +794,51 @@ fn siblings<P>(passes: &[PassBuilder<P>], pass: &PassBuilder<P>) -> Vec<usize> {
     siblings
 }
 
+/// Get attachments the pass writes to.
+fn outputs<P>(pass: &PassBuilder<P>) -> Vec<AttachmentRef> {
+    pass.colors
+        .iter()
+        .map(|&(a, _)| a)
+        .chain(written_depth(pass))
+        .chain(
+            pass.storages
+                .iter()
+                .filter(|&&(_, written)| written)
+                .map(|&(a, _)| a),
+        )
+        .collect()
+}
+
+/// Get depth-stencil attachment the pass writes to.
+fn written_depth<P>(pass: &PassBuilder<P>) -> Option<AttachmentRef> {
+    match pass.depth_stencil {
+        Some((a, _)) if !pass.depth_read_only => Some(a),
+        _ => None,
+    }
+}
+
+/// Get depth-stencil attachment the pass tests against without writing.
+fn read_only_depth<P>(pass: &PassBuilder<P>) -> Option<&AttachmentRef> {
+    match pass.depth_stencil {
+        Some((ref a, _)) if pass.depth_read_only => Some(a),
+        _ => None,
+    }
+}
+
 /// Get dependencies of pass. And dependencies of dependencies.
 fn dependencies<P>(passes: &[PassBuilder<P>], pass: &PassBuilder<P>) -> Vec<usize> {
     let mut deps = direct_dependencies(passes, pass);
-    deps = deps.into_iter()
-        .flat_map(|dep| dependencies(passes, &passes[dep]))
-        .collect();
+    // Walk over dependencies found so far, circular dependencies are visited once
+    let mut next = 0;
+    while next < deps.len() {
+        for dep in direct_dependencies(passes, &passes[deps[next]]) {
+            if !deps.contains(&dep) {
+                deps.push(dep);
+            }
+        }
+        next += 1;
+    }
     deps.sort();
-    deps.dedup();
     deps
 }
 
@@ -611,3 +881,52 @@ where
     }
     Ok(())
 }
+
+#[test]
+fn test_explicit_dependency() {
+    use pass::PassDesc;
+    use vertex::VertexFormat;
+
+    #[derive(Debug)]
+    struct Empty(&'static str);
+
+    impl PassDesc for Empty {
+        fn name(&self) -> &str {
+            self.0
+        }
+        fn sampled(&self) -> usize {
+            0
+        }
+        fn storage(&self) -> usize {
+            0
+        }
+        fn inputs(&self) -> usize {
+            0
+        }
+        fn colors(&self) -> usize {
+            0
+        }
+        fn depth(&self) -> bool {
+            false
+        }
+        fn stencil(&self) -> bool {
+            false
+        }
+        fn vertices(&self) -> &[VertexFormat] {
+            &[]
+        }
+        fn bindings(&self) -> &[&[::gfx_hal::pso::DescriptorSetLayoutBinding]] {
+            &[]
+        }
+    }
+
+    let mut graph = GraphBuilder::new();
+    graph.add_pass(PassBuilder::new(Empty("X")));
+    let a = graph.add_pass(PassBuilder::new(Empty("A")));
+    graph.add_pass(PassBuilder::new(Empty("B")).with_dependency(a));
+
+    let (passes, deps) = reorder_passes(graph.passes).unwrap();
+    let names = passes.iter().map(|pass| pass.name()).collect::<Vec<_>>();
+    assert_eq!(names, ["X", "A", "B"]);
+    assert_eq!(deps, [None, None, Some(1)]);
+}
@@ -11,20 +11,28 @@ pub use self::build::{GraphBuildError, GraphBuilder};
 
 use std::borrow::Borrow;
 use std::ops::Range;
+use std::time::Duration;
 
 use gfx_hal::{Backend, Device};
-use gfx_hal::command::{OneShot, Viewport};
+use gfx_hal::command::{ClearValue, OneShot, Primary, Submit, Viewport};
 use gfx_hal::pool::CommandPool;
-use gfx_hal::pso::PipelineStage;
+use gfx_hal::pso::{CreationError, PipelineStage};
 use gfx_hal::queue::CommandQueue;
 use gfx_hal::queue::capability::{Graphics, Supports, Transfer};
 
+use rayon::prelude::*;
+
 use smallvec::SmallVec;
 
+use attachment::AttachmentRef;
+use debug::DebugMarkers;
 use frame::SuperFrame;
-use pass::{Pass, PassNode};
+use pass::{create_graphics_pipelines, Pass, PassDesc, PassNode, PassShaders};
+
+use self::timestamps::Timestamps;
 
 mod build;
+mod timestamps;
 
 /// Directed acyclic rendering graph.
 ///
@@ -43,8 +51,13 @@ pub struct Graph<B: Backend, I, P> {
     signals: Vec<Option<B::Semaphore>>,
     images: Vec<I>,
     views: Vec<B::ImageView>,
+    samplers: Vec<B::Sampler>,
+    timestamps: Option<Timestamps<B>>,
+    markers: Box<DebugMarkers<B>>,
     frames: usize,
     draws_to_surface: Range<usize>,
+    parallel_pipelines: bool,
+    clears: Vec<Option<ClearValue>>,
 }
 
 impl<B, I, P> Graph<B, I, P>
@@ -75,7 +88,7 @@ where
     ///             (or `Framebuffer` reference that corresponds to index `0`)
     /// - `acquire`: surface acquisition semaphore.
     /// - `release`: presentation will wait on this.
-    /// - `viewport`: portion of framebuffers to draw to.
+    /// - `viewport`: portion of framebuffers to draw to, unless overridden by `RenderArea`.
     /// - `finish`: last submission should set this fence.
     ///             Application must wait for this fence before calling `draw_inline` again with same `frame`.
     /// - `device`: you need this guy everywhere =^_^=
@@ -100,12 +113,13 @@ where
         P: Pass<B, T>,
         I: Borrow<B::Image>,
     {
-        use gfx_hal::queue::submission::Submission;
-
         let ref signals = self.signals;
         let count = self.passes.len();
         let ref draws_to_surface = self.draws_to_surface;
         let ref images = self.images;
+        let ref samplers = self.samplers;
+        let ref timestamps = self.timestamps;
+        let ref markers = self.markers;
 
         // Record commands for all passes
         self.passes.iter_mut().enumerate().for_each(|(id, pass)| {
@@ -113,55 +127,397 @@ where
             let mut cbuf = pool.acquire_command_buffer::<OneShot>(false);
 
             // Setup
+            let viewport = pass.viewport(&viewport, frame, aux);
             cbuf.set_viewports(&[viewport.clone()]);
             cbuf.set_scissors(&[viewport.rect]);
 
             // Record commands for pass
-            pass.prepare(&mut cbuf, device, images, frame, aux);
-            pass.draw_inline(&mut cbuf, device, images, viewport.rect, frame, aux);
-
-            {
-                // If it renders to acquired image
-                let wait_surface = if id == draws_to_surface.start {
-                    // And it should wait for acquisition
-                    Some((acquire, PipelineStage::TOP_OF_PIPE))
-                } else {
-                    None
-                };
-
-                let to_wait = pass.depends
-                    .as_ref()
-                    .map(|&(id, stage)| (signals[id].as_ref().unwrap(), stage))
-                    .into_iter()
-                    .chain(wait_surface)
-                    .collect::<SmallVec<[_; 3]>>();
-
-                let mut to_signal = SmallVec::<[_; 1]>::new();
-                if id == draws_to_surface.end {
-                    to_signal.push(release);
+            markers.begin_group(cbuf.downgrade(), pass.name());
+            if pass.is_enabled() {
+                pass.prepare(&mut cbuf, device, images, samplers, frame, aux);
+            }
+            if let Some(ref timestamps) = *timestamps {
+                timestamps.begin(&mut cbuf, frame.index(), id);
+            }
+            pass.storage_barriers(&mut cbuf, images, frame);
+            if pass.is_enabled() {
+                pass.draw_inline(
+                    &mut cbuf,
+                    device,
+                    images,
+                    samplers,
+                    viewport.rect,
+                    frame,
+                    aux,
+                );
+            } else {
+                pass.draw_disabled(&mut cbuf, images, viewport.rect, frame);
+            }
+            if let Some(ref timestamps) = *timestamps {
+                timestamps.end(&mut cbuf, frame.index(), id);
+            }
+            markers.end_group(cbuf.downgrade());
+
+            submit(
+                queue,
+                Some(cbuf.finish()),
+                id,
+                count,
+                pass.depends,
+                signals,
+                draws_to_surface,
+                acquire,
+                release,
+                finish,
+            );
+        });
+    }
+
+    /// Walk over graph recording drawing commands in parallel and submitting them to `queue`.
+    /// This function handles synchronization between dependent rendering nodes.
+    ///
+    /// Preparation is executed sequentially for all passes first.
+    /// Then chunks of all passes (see `Pass::chunks`) are distributed between `workers`
+    /// and recorded into secondary command buffers on the `rayon` thread pool.
+    /// Finally the chunks are executed in primary command buffers from `pool`.
+    /// Passes that don't split drawing commands into chunks are recorded with `Pass::draw_inline`
+    /// directly into primary command buffers.
+    ///
+    /// `queue` must come from same `QueueGroup` with which `pool` and all `workers` are associated.
+    /// All those should be created by `device`.
+    ///
+    /// ### Parameters
+    ///
+    /// - `queue`: queue that commands will be submitted to
+    /// - `pool`: command buffer pool to use for primary command buffers
+    /// - `workers`: command buffer pools to use for secondary command buffers, one per thread
+    /// - `frame`: frame index that should be drawn.
+    ///             (or `Framebuffer` reference that corresponds to index `0`)
+    /// - `acquire`: surface acquisition semaphore.
+    /// - `release`: presentation will wait on this.
+    /// - `viewport`: portion of framebuffers to draw to, unless overridden by `RenderArea`.
+    /// - `finish`: last submission should set this fence.
+    ///             Application must wait for this fence before calling `draw_parallel` again with same `frame`.
+    /// - `device`: you need this guy everywhere =^_^=
+    /// - `aux`: auxiliary data for passes.
+    ///
+    /// ### Type parameters:
+    ///
+    /// - `C`: hal `Capability`
+    pub fn draw_parallel<C, T>(
+        &mut self,
+        queue: &mut CommandQueue<B, C>,
+        pool: &mut CommandPool<B, C>,
+        workers: &mut [CommandPool<B, C>],
+        frame: SuperFrame<B>,
+        acquire: &B::Semaphore,
+        release: &B::Semaphore,
+        viewport: Viewport,
+        finish: &B::Fence,
+        device: &B::Device,
+        aux: &mut T,
+    ) where
+        C: Supports<Graphics> + Supports<Transfer> + Send,
+        P: Pass<B, T> + Sync,
+        T: Sync,
+        I: Borrow<B::Image> + Sync,
+    {
+        assert!(!workers.is_empty(), "At least one worker pool is required");
+
+        let ref signals = self.signals;
+        let count = self.passes.len();
+        let ref draws_to_surface = self.draws_to_surface;
+        let ref images = self.images;
+        let ref samplers = self.samplers;
+        let ref timestamps = self.timestamps;
+        let ref markers = self.markers;
+
+        // Run preparation for all passes sequentially as it requires exclusive access to `aux`
+        let mut prepared = self.passes
+            .iter_mut()
+            .map(|pass| {
+                if !pass.is_enabled() {
+                    return None;
                 }
-                if let Some(signal) = signals[id].as_ref() {
-                    to_signal.push(signal);
+                let mut cbuf = pool.acquire_command_buffer::<OneShot>(false);
+                markers.begin_group(cbuf.downgrade(), pass.name());
+                pass.prepare(&mut cbuf, device, images, samplers, frame, aux);
+                markers.end_group(cbuf.downgrade());
+                Some(cbuf.finish())
+            })
+            .collect::<Vec<_>>();
+
+        let aux: &T = aux;
+        let (viewports, counts, mut chunks) = {
+            let ref passes = self.passes;
+            let viewports = passes
+                .iter()
+                .map(|pass| pass.viewport(&viewport, frame, aux))
+                .collect::<Vec<_>>();
+
+            // Passes without chunks are recorded inline into primary command buffers
+            let counts = passes
+                .iter()
+                .map(|pass| pass.chunks(frame, aux))
+                .collect::<Vec<_>>();
+
+            // Collect all chunks of all enabled passes
+            let jobs = passes
+                .iter()
+                .enumerate()
+                .filter(|&(_, pass)| pass.is_enabled())
+                .flat_map(|(id, _)| (0..counts[id]).map(move |chunk| (id, chunk)))
+                .collect::<Vec<_>>();
+
+            // Record chunks distributing them between workers
+            let workers_count = workers.len();
+            let chunks = workers
+                .par_iter_mut()
+                .enumerate()
+                .map(|(worker, pool)| {
+                    jobs.iter()
+                        .skip(worker)
+                        .step_by(workers_count)
+                        .map(|&(id, chunk)| {
+                            let submit = passes[id].draw_chunk(
+                                pool,
+                                device,
+                                images,
+                                samplers,
+                                &viewports[id],
+                                chunk,
+                                frame,
+                                aux,
+                            );
+                            (id, chunk, submit)
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .reduce(Vec::new, |mut left, right| {
+                    left.extend(right);
+                    left
+                });
+            (viewports, counts, chunks)
+        };
+
+        // Restore order of chunks
+        chunks.sort_by_key(|&(id, chunk, _)| (id, chunk));
+        let mut chunks = chunks.into_iter().peekable();
+
+        // Execute chunks and submit
+        for (id, pass) in self.passes.iter_mut().enumerate() {
+            let ref viewport = viewports[id];
+            let mut cbuf = pool.acquire_command_buffer::<OneShot>(false);
+            cbuf.set_viewports(&[viewport.clone()]);
+            cbuf.set_scissors(&[viewport.rect]);
+
+            let mut pass_chunks = SmallVec::<[_; 16]>::new();
+            while chunks.peek().map_or(false, |&(chunk_id, _, _)| chunk_id == id) {
+                pass_chunks.push(chunks.next().unwrap().2);
+            }
+            markers.begin_group(cbuf.downgrade(), pass.name());
+            if let Some(ref timestamps) = *timestamps {
+                timestamps.begin(&mut cbuf, frame.index(), id);
+            }
+            pass.storage_barriers(&mut cbuf, images, frame);
+            if pass.is_enabled() && counts[id] == 0 {
+                pass.draw_inline(
+                    &mut cbuf,
+                    device,
+                    images,
+                    samplers,
+                    viewport.rect,
+                    frame,
+                    aux,
+                );
+            } else if pass.is_enabled() {
+                pass.execute_chunks(&mut cbuf, viewport.rect, frame, pass_chunks);
+            } else {
+                pass.draw_disabled(&mut cbuf, images, viewport.rect, frame);
+            }
+            if let Some(ref timestamps) = *timestamps {
+                timestamps.end(&mut cbuf, frame.index(), id);
+            }
+            markers.end_group(cbuf.downgrade());
+
+            submit(
+                queue,
+                prepared[id].take().into_iter().chain(Some(cbuf.finish())),
+                id,
+                count,
+                pass.depends,
+                signals,
+                draws_to_surface,
+                acquire,
+                release,
+                finish,
+            );
+        }
+    }
+
+    /// Enable or disable all passes with the given name without rebuilding the graph.
+    ///
+    /// Disabled pass doesn't record preparation and drawing commands but its renderpass is still
+    /// executed, so attachments are cleared and transitioned as usual and synchronization with
+    /// other passes stays intact. Attachment set with `PassBuilder::with_forward`
+    /// is copied to the output of the disabled pass.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `name`: name of the pass as returned by `PassDesc::name`
+    /// - `enabled`: should the pass draw anything
+    pub fn set_pass_enabled(&mut self, name: &str, enabled: bool)
+    where
+        P: PassDesc,
+    {
+        let mut found = false;
+        for pass in self.passes.iter_mut().filter(|pass| pass.name() == name) {
+            pass.set_enabled(enabled);
+            found = true;
+        }
+        if !found {
+            warn!("Pass {} is not found in the graph", name);
+        }
+    }
+
+    /// Load shaders of all passes again and recreate their graphics pipelines.
+    /// Passes keep old pipelines if new ones can't be created.
+    ///
+    /// Old pipelines are destroyed immediately,
+    /// so the device must not execute commands recorded by the graph.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: graphics device
+    ///
+    /// ### Returns
+    ///
+    /// First error encountered. Shaders of all passes are reloaded regardless.
+    pub fn reload_shaders(&mut self, device: &B::Device) -> Result<(), CreationError>
+    where
+        P: PassShaders<B>,
+    {
+        self.reload_passes(|_| true, device)
+    }
+
+    /// Load shaders of the pass again and recreate its graphics pipelines.
+    /// Pass keeps old pipelines if new ones can't be created.
+    /// `ShaderWatcher` calls this for passes which shader files were modified.
+    ///
+    /// Same restrictions as for `Graph::reload_shaders` apply.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `name`: name of the pass as returned by `PassDesc::name`
+    /// - `device`: graphics device
+    pub fn reload_pass_shaders(
+        &mut self,
+        name: &str,
+        device: &B::Device,
+    ) -> Result<(), CreationError>
+    where
+        P: PassShaders<B>,
+    {
+        if self.passes.iter().all(|pass| pass.name() != name) {
+            warn!("Pass {} is not found in the graph", name);
+        }
+        self.reload_passes(|pass| pass.name() == name, device)
+    }
+
+    fn reload_passes<F>(&mut self, filter: F, device: &B::Device) -> Result<(), CreationError>
+    where
+        F: Fn(&PassNode<B, P>) -> bool,
+        P: PassShaders<B>,
+    {
+        let pipelines = create_graphics_pipelines(
+            &self.passes.iter().filter(|pass| filter(pass)).collect::<Vec<_>>(),
+            self.parallel_pipelines,
+            device,
+        );
+
+        let mut result = Ok(());
+        let passes = self.passes.iter_mut().filter(|pass| filter(pass));
+        for (pass, pipelines) in passes.zip(pipelines) {
+            match pipelines {
+                Ok(pipelines) => pass.set_graphics_pipelines(pipelines, device),
+                Err(error) => {
+                    error!("Failed to reload shaders of pass {}: {:?}", pass.name(), error);
+                    result = result.and(Err(error));
                 }
+            }
+        }
+        result
+    }
 
-                // Signal the finish fence in last submission
-                let fence = if id == count - 1 { Some(finish) } else { None };
-
-                // Submit buffer
-                queue.submit(
-                    Submission::new()
-                        .promote::<C>()
-                        .submit(Some(cbuf.finish()))
-                        .wait_on(&to_wait)
-                        .signal(&to_signal),
-                    fence,
+    /// Set value the attachment is cleared with.
+    /// New value is used for all frames drawn after this call.
+    ///
+    /// Only attachments declared with clear value are cleared by the graph.
+    /// Setting clear value for other attachments or value that doesn't match
+    /// the attachment aspects is an error and is ignored.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `attachment`: attachment to set clear value for
+    /// - `clear`: new clear value, must match the aspects of the attachment format
+    pub fn set_clear(&mut self, attachment: AttachmentRef, clear: ClearValue) {
+        let matches = match (self.clears[attachment.index()], clear) {
+            (None, _) => {
+                error!("Attachment {:?} is not cleared by the graph", attachment);
+                false
+            }
+            (Some(ClearValue::Color(_)), ClearValue::Color(_))
+            | (Some(ClearValue::DepthStencil(_)), ClearValue::DepthStencil(_)) => true,
+            (Some(_), _) => {
+                error!(
+                    "Clear value {:?} doesn't match aspects of attachment {:?}",
+                    clear, attachment
                 );
+                false
             }
-        });
+        };
+        debug_assert!(matches, "Invalid clear value for attachment {:?}", attachment);
+        if !matches {
+            return;
+        }
+
+        self.clears[attachment.index()] = Some(clear);
+        for pass in &mut self.passes {
+            pass.set_clear(attachment, clear);
+        }
+    }
+
+    /// Get GPU time spent in render pass of each pass during the last drawing of the `frame`.
+    /// Durations are paired with `PassDesc::name` of the passes in order of submission.
+    ///
+    /// Returns `None` if profiling wasn't enabled with `GraphBuilder::with_profiling`
+    /// or results are not available yet.
+    /// Results become available after the `finish` fence of the `frame` has signalled.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `frame`: frame index to get timings for
+    /// - `device`: graphics device
+    pub fn pass_timings(
+        &self,
+        frame: SuperFrame<B>,
+        device: &B::Device,
+    ) -> Option<Vec<(&str, Duration)>>
+    where
+        P: PassDesc,
+    {
+        let durations = self.timestamps.as_ref()?.durations(device, frame.index())?;
+        Some(
+            self.passes
+                .iter()
+                .map(PassNode::name)
+                .zip(durations)
+                .collect(),
+        )
     }
 
     /// Dispose of the graph, will call `dispose` on all passes, and clean up any `Semaphore`s,
-    /// `ImageView`s, and images created by the `Graph` or `GraphBuilder`.
+    /// `ImageView`s, `Sampler`s and images created by the `Graph` or `GraphBuilder`.
     ///
     /// ### Parameters:
     ///
@@ -187,12 +543,73 @@ where
         for view in self.views {
             device.destroy_image_view(view);
         }
+        for sampler in self.samplers {
+            device.destroy_sampler(sampler);
+        }
+        if let Some(timestamps) = self.timestamps {
+            timestamps.dispose(device);
+        }
         for image in self.images {
             deallocator(image, device);
         }
     }
 }
 
+/// Submit command buffers recorded for the pass `id`,
+/// waiting for the pass it depends on and signaling its own semaphore.
+fn submit<B, C, J>(
+    queue: &mut CommandQueue<B, C>,
+    cbufs: J,
+    id: usize,
+    count: usize,
+    depends: Option<(usize, PipelineStage)>,
+    signals: &[Option<B::Semaphore>],
+    draws_to_surface: &Range<usize>,
+    acquire: &B::Semaphore,
+    release: &B::Semaphore,
+    finish: &B::Fence,
+) where
+    B: Backend,
+    J: IntoIterator<Item = Submit<B, C, OneShot, Primary>>,
+{
+    use gfx_hal::queue::submission::Submission;
+
+    // If it renders to acquired image
+    let wait_surface = if id == draws_to_surface.start {
+        // And it should wait for acquisition
+        Some((acquire, PipelineStage::TOP_OF_PIPE))
+    } else {
+        None
+    };
+
+    let to_wait = depends
+        .map(|(id, stage)| (signals[id].as_ref().unwrap(), stage))
+        .into_iter()
+        .chain(wait_surface)
+        .collect::<SmallVec<[_; 3]>>();
+
+    let mut to_signal = SmallVec::<[_; 1]>::new();
+    if id == draws_to_surface.end {
+        to_signal.push(release);
+    }
+    if let Some(signal) = signals[id].as_ref() {
+        to_signal.push(signal);
+    }
+
+    // Signal the finish fence in last submission
+    let fence = if id == count - 1 { Some(finish) } else { None };
+
+    // Submit buffers
+    queue.submit(
+        Submission::new()
+            .promote::<C>()
+            .submit(cbufs)
+            .wait_on(&to_wait)
+            .signal(&to_signal),
+        fence,
+    );
+}
+
 #[test]
 #[allow(dead_code)]
 fn test_send_sync() {
@@ -0,0 +1,112 @@
+//!
+//! GPU timestamps written around render passes of the `Graph`.
+//!
+
+use std::mem::size_of;
+use std::ops::Range;
+use std::slice;
+use std::time::Duration;
+
+use gfx_hal::{Backend, Device};
+use gfx_hal::command::CommandBuffer;
+use gfx_hal::pso::PipelineStage;
+use gfx_hal::query::{Query, QueryId, QueryResultFlags, QueryType};
+
+/// Timestamp query pool with a pair of queries for each pass in each frame.
+#[derive(Debug)]
+pub(crate) struct Timestamps<B: Backend> {
+    pool: B::QueryPool,
+    period: f32,
+    passes: usize,
+}
+
+impl<B> Timestamps<B>
+where
+    B: Backend,
+{
+    /// Create query pool big enough for all passes in all frames.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: graphics device
+    /// - `passes`: number of passes in the graph
+    /// - `frames`: number of frames in the graph
+    /// - `period`: number of nanoseconds per timestamp tick
+    pub fn new(device: &B::Device, passes: usize, frames: usize, period: f32) -> Self {
+        Timestamps {
+            pool: device.create_query_pool(QueryType::Timestamp, (passes * frames * 2) as u32),
+            period,
+            passes,
+        }
+    }
+
+    /// Reset queries of the pass and write timestamp before its render pass begins.
+    pub fn begin<C>(&self, cbuf: &mut CommandBuffer<B, C>, frame: usize, pass: usize) {
+        let queries = self.queries(frame, pass);
+        cbuf.reset_query_pool(&self.pool, queries.clone());
+        cbuf.write_timestamp(
+            PipelineStage::TOP_OF_PIPE,
+            Query {
+                pool: &self.pool,
+                id: queries.start,
+            },
+        );
+    }
+
+    /// Write timestamp after the render pass of the pass ends.
+    pub fn end<C>(&self, cbuf: &mut CommandBuffer<B, C>, frame: usize, pass: usize) {
+        cbuf.write_timestamp(
+            PipelineStage::BOTTOM_OF_PIPE,
+            Query {
+                pool: &self.pool,
+                id: self.queries(frame, pass).start + 1,
+            },
+        );
+    }
+
+    /// Read durations of all passes in the frame.
+    /// Returns `None` if results are not available.
+    pub fn durations(&self, device: &B::Device, frame: usize) -> Option<Vec<Duration>> {
+        let queries = self.queries(frame, 0).start..self.queries(frame + 1, 0).start;
+        let mut ticks = vec![0u64; self.passes * 2];
+        let available = {
+            let data = unsafe {
+                slice::from_raw_parts_mut(
+                    ticks.as_mut_ptr() as *mut u8,
+                    ticks.len() * size_of::<u64>(),
+                )
+            };
+            device.get_query_pool_results(
+                &self.pool,
+                queries,
+                data,
+                size_of::<u64>() as u64,
+                QueryResultFlags::BITS_64,
+            )
+        };
+
+        match available {
+            Ok(true) => Some(
+                ticks
+                    .chunks(2)
+                    .map(|pair| {
+                        let nanos = (pair[1].saturating_sub(pair[0]) as f64
+                            * self.period as f64) as u64;
+                        Duration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Destroy query pool.
+    pub fn dispose(self, device: &B::Device) {
+        device.destroy_query_pool(self.pool);
+    }
+
+    fn queries(&self, frame: usize, pass: usize) -> Range<QueryId> {
+        let start = ((frame * self.passes + pass) * 2) as QueryId;
+        start..start + 2
+    }
+}
@@ -16,17 +16,37 @@ extern crate derivative;
 extern crate gfx_hal;
 #[macro_use]
 extern crate log;
+extern crate rayon;
 extern crate relevant;
 extern crate smallvec;
 
 pub use attachment::{Attachment, ColorAttachment, DepthStencilAttachment};
+pub use debug::{DebugMarkers, NoDebugMarkers};
 pub use descriptors::DescriptorPool;
 pub use frame::SuperFrame;
 pub use graph::{Graph, GraphBuildError, GraphBuilder};
-pub use pass::{Pass, PassBuilder, PassDesc, PassShaders};
+pub use pass::{Pass, PassBuilder, PassDesc, PassRef, PassShaders, RenderArea};
+#[cfg(feature = "reflection")]
+pub use reflect::ShaderInterface;
+pub use shaders::ShaderModules;
+pub use vertex::{Vertex, VertexAttribute, VertexFormat};
+pub use watch::ShaderWatcher;
 
 mod attachment;
+mod debug;
 mod descriptors;
 mod graph;
 mod pass;
 mod frame;
+#[cfg(feature = "reflection")]
+mod reflect;
+mod shaders;
+mod vertex;
+mod watch;
+
+/// Items used by code generated with `xfg-derive`. Not a public API.
+#[doc(hidden)]
+pub mod __derive {
+    pub use gfx_hal::format::Format;
+    pub use gfx_hal::pso::{DescriptorSetLayoutBinding, Element};
+}
@@ -1,18 +1,31 @@
+use std::ops::Range;
+use std::sync::Arc;
+
 use gfx_hal::{Backend, Device, Primitive};
 use gfx_hal::command::{ClearColor, ClearDepthStencil, ClearValue};
 use gfx_hal::device::Extent;
-use gfx_hal::format::Format;
 use gfx_hal::image;
 use gfx_hal::pass;
 use gfx_hal::pso;
 
-use smallvec::SmallVec;
-
 use attachment::{AttachmentDesc, AttachmentRef};
 use descriptors::DescriptorPool;
 use frame::SuperFramebuffer;
 use graph::GraphBuildError;
-use pass::{PassDesc, PassNode, PassShaders};
+use pass::{PassDesc, PassNode, PassShaders, RenderArea};
+#[cfg(feature = "reflection")]
+use reflect::ShaderInterface;
+use shaders::{ShaderCache, ShaderModules};
+use vertex::{attribute_locations, VertexFormat};
+
+/// Reference to the pass added to `GraphBuilder`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PassRef(pub(crate) usize);
+impl PassRef {
+    pub(crate) fn index(&self) -> usize {
+        self.0
+    }
+}
 
 /// Collection of data required to construct the node in the rendering `Graph` for a single `Pass`
 ///
@@ -22,11 +35,18 @@ use pass::{PassDesc, PassNode, PassShaders};
 /// - `T`: auxiliary data used by the inner `Pass`
 #[derive(Debug)]
 pub struct PassBuilder<P> {
-    pub(crate) sampled: Vec<AttachmentRef>,
-    pub(crate) storages: Vec<AttachmentRef>,
+    pub(crate) sampled: Vec<(AttachmentRef, image::SamplerInfo)>,
+    pub(crate) storages: Vec<(AttachmentRef, bool)>,
     pub(crate) inputs: Vec<AttachmentRef>,
     pub(crate) colors: Vec<(AttachmentRef, pso::ColorBlendDesc)>,
     pub(crate) depth_stencil: Option<(AttachmentRef, pso::DepthStencilDesc)>,
+    pub(crate) depth_read_only: bool,
+    stencil_test: Option<pso::StencilTest>,
+    pub(crate) forward: Option<(AttachmentRef, AttachmentRef)>,
+    render_area: RenderArea,
+    pub(crate) dependencies: Vec<PassRef>,
+    pub(crate) index: usize,
+    specializations: Vec<(pso::Stage, Vec<pso::Specialization>)>,
     rasterizer: pso::Rasterizer,
     primitive: Primitive,
     pass: P,
@@ -44,29 +64,65 @@ where
             inputs: Vec::new(),
             colors: Vec::new(),
             depth_stencil: None,
-            rasterizer: pso::Rasterizer::FILL,
-            primitive: Primitive::TriangleList,
+            depth_read_only: false,
+            stencil_test: None,
+            forward: None,
+            render_area: RenderArea::default(),
+            dependencies: Vec::new(),
+            index: 0,
+            specializations: Vec::new(),
+            rasterizer: pass.rasterizer().unwrap_or(pso::Rasterizer::FILL),
+            primitive: pass.primitive().unwrap_or(Primitive::TriangleList),
             pass,
         }
     }
 
     /// Specify attachment to be sampled in pass.
+    /// Attachment will be sampled with bilinear filtering and clamped coordinates.
     ///
     /// ### Parameters:
     ///
     /// - `input`: attachment to use
     pub fn with_sampled(mut self, input: AttachmentRef) -> Self {
-        self.sampled.push(input);
+        self.add_sampled(input);
         self
     }
 
     /// Specify attachment to be sampled in pass.
+    /// Attachment will be sampled with bilinear filtering and clamped coordinates.
     ///
     /// ### Parameters:
     ///
     /// - `input`: attachment to use
     pub fn add_sampled(&mut self, input: AttachmentRef) -> &mut Self {
-        self.sampled.push(input);
+        self.add_sampled_desc(input, default_sampler_info())
+    }
+
+    /// Specify attachment to be sampled in pass with sampler described by `info`.
+    /// `Graph` creates the sampler and shares it between all passes with the same description.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `input`: attachment to use
+    /// - `info`: sampler description
+    pub fn with_sampled_desc(mut self, input: AttachmentRef, info: image::SamplerInfo) -> Self {
+        self.add_sampled_desc(input, info);
+        self
+    }
+
+    /// Specify attachment to be sampled in pass with sampler described by `info`.
+    /// `Graph` creates the sampler and shares it between all passes with the same description.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `input`: attachment to use
+    /// - `info`: sampler description
+    pub fn add_sampled_desc(
+        &mut self,
+        input: AttachmentRef,
+        info: image::SamplerInfo,
+    ) -> &mut Self {
+        self.sampled.push((input, info));
         self
     }
 
@@ -76,7 +132,7 @@ where
     ///
     /// - `input`: attachment to use
     pub fn with_storage(mut self, input: AttachmentRef) -> Self {
-        self.storages.push(input);
+        self.storages.push((input, false));
         self
     }
 
@@ -86,7 +142,31 @@ where
     ///
     /// - `input`: attachment to use
     pub fn add_storage(&mut self, input: AttachmentRef) -> &mut Self {
-        self.storages.push(input);
+        self.storages.push((input, false));
+        self
+    }
+
+    /// Specify attachment to be written as storage in pass.
+    /// Pass can read content written by previous passes as well.
+    /// Passes that write the same attachment don't overlap.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `storage`: attachment to use
+    pub fn with_storage_write(mut self, storage: AttachmentRef) -> Self {
+        self.storages.push((storage, true));
+        self
+    }
+
+    /// Specify attachment to be written as storage in pass.
+    /// Pass can read content written by previous passes as well.
+    /// Passes that write the same attachment don't overlap.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `storage`: attachment to use
+    pub fn add_storage_write(&mut self, storage: AttachmentRef) -> &mut Self {
+        self.storages.push((storage, true));
         self
     }
 
@@ -168,7 +248,7 @@ where
         depth_stencil: AttachmentRef,
         desc: pso::DepthStencilDesc,
     ) -> Self {
-        self.depth_stencil = Some((depth_stencil, desc));
+        self.set_depth_stencil_desc(depth_stencil, desc);
         self
     }
 
@@ -185,6 +265,7 @@ where
         desc: pso::DepthStencilDesc,
     ) -> &mut Self {
         self.depth_stencil = Some((depth_stencil, desc));
+        self.depth_read_only = false;
         self
     }
 
@@ -196,7 +277,7 @@ where
     ///
     /// - `depth_stencil`: attachment to use
     pub fn with_depth_stencil(mut self, depth_stencil: AttachmentRef) -> Self {
-        self.depth_stencil = Some((depth_stencil, depth_stencil_desc(&self.pass)));
+        self.set_depth_stencil(depth_stencil);
         self
     }
 
@@ -208,7 +289,226 @@ where
     ///
     /// - `depth_stencil`: attachment to use
     pub fn set_depth_stencil(&mut self, depth_stencil: AttachmentRef) -> &mut Self {
-        self.depth_stencil = Some((depth_stencil, depth_stencil_desc(&self.pass)));
+        let desc = depth_stencil_desc(&self.pass);
+        self.set_depth_stencil_desc(depth_stencil, desc)
+    }
+
+    /// Set the depth stencil attachment to test against without writing to it.
+    /// The pass only reads the attachment written by earlier passes, so passes that
+    /// read the same attachment can overlap. The attachment can be sampled by the pass
+    /// at the same time. Stencil test is still performed but never modifies the stencil.
+    ///
+    /// Will only be set if the actual `Pass` is configured to use the depth stencil buffer.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `depth_stencil`: attachment to use
+    pub fn with_depth_read_only(mut self, depth_stencil: AttachmentRef) -> Self {
+        self.set_depth_read_only(depth_stencil);
+        self
+    }
+
+    /// Set the depth stencil attachment to test against without writing to it.
+    /// The pass only reads the attachment written by earlier passes, so passes that
+    /// read the same attachment can overlap. The attachment can be sampled by the pass
+    /// at the same time. Stencil test is still performed but never modifies the stencil.
+    ///
+    /// Will only be set if the actual `Pass` is configured to use the depth stencil buffer.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `depth_stencil`: attachment to use
+    pub fn set_depth_read_only(&mut self, depth_stencil: AttachmentRef) -> &mut Self {
+        let mut desc = depth_stencil_desc(&self.pass);
+        if let pso::DepthTest::On { ref mut write, .. } = desc.depth {
+            *write = false;
+        }
+        self.depth_stencil = Some((depth_stencil, desc));
+        self.depth_read_only = true;
+        self
+    }
+
+    /// Set the stencil test state of the pass.
+    /// Overrides the stencil test of the depth-stencil description.
+    ///
+    /// Requires `PassDesc::stencil` and depth-stencil attachment with stencil aspect.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `stencil_test`: stencil test state
+    pub fn with_stencil_test(mut self, stencil_test: pso::StencilTest) -> Self {
+        self.set_stencil_test(stencil_test);
+        self
+    }
+
+    /// Set the stencil test state of the pass.
+    /// Overrides the stencil test of the depth-stencil description.
+    ///
+    /// Requires `PassDesc::stencil` and depth-stencil attachment with stencil aspect.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `stencil_test`: stencil test state
+    pub fn set_stencil_test(&mut self, stencil_test: pso::StencilTest) -> &mut Self {
+        self.stencil_test = Some(stencil_test);
+        self
+    }
+
+    /// Forward content of the `from` attachment to the `to` attachment
+    /// while the pass is disabled with `Graph::set_pass_enabled`.
+    /// Passes that follow will see the input of the disabled pass instead of its output.
+    ///
+    /// `from` must be sampled or storage attachment of the pass and `to` its color attachment
+    /// of the same format. `to` can't be the presentation attachment.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `from`: attachment to copy from
+    /// - `to`: attachment to copy to
+    pub fn with_forward(mut self, from: AttachmentRef, to: AttachmentRef) -> Self {
+        self.set_forward(from, to);
+        self
+    }
+
+    /// Forward content of the `from` attachment to the `to` attachment
+    /// while the pass is disabled with `Graph::set_pass_enabled`.
+    /// Passes that follow will see the input of the disabled pass instead of its output.
+    ///
+    /// `from` must be sampled or storage attachment of the pass and `to` its color attachment
+    /// of the same format. `to` can't be the presentation attachment.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `from`: attachment to copy from
+    /// - `to`: attachment to copy to
+    pub fn set_forward(&mut self, from: AttachmentRef, to: AttachmentRef) -> &mut Self {
+        self.forward = Some((from, to));
+        self
+    }
+
+    /// Make the pass depend on another pass without shared attachments.
+    /// E.g. when the pass uses resources written by the other pass that aren't managed
+    /// by the `Graph`. The pass is scheduled after the dependency and waits for it to complete.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `pass`: pass returned by `GraphBuilder::add_pass`
+    pub fn with_dependency(mut self, pass: PassRef) -> Self {
+        self.dependencies.push(pass);
+        self
+    }
+
+    /// Make the pass depend on another pass without shared attachments.
+    /// E.g. when the pass uses resources written by the other pass that aren't managed
+    /// by the `Graph`. The pass is scheduled after the dependency and waits for it to complete.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `pass`: pass returned by `GraphBuilder::add_pass`
+    pub fn add_dependency(&mut self, pass: PassRef) -> &mut Self {
+        self.dependencies.push(pass);
+        self
+    }
+
+    /// Set the rasterizer state of the pass:
+    /// polygon mode with line width, face culling, front face, depth clamping and depth bias.
+    /// Overrides `PassDesc::rasterizer`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `rasterizer`: rasterizer state
+    pub fn with_rasterizer(mut self, rasterizer: pso::Rasterizer) -> Self {
+        self.set_rasterizer(rasterizer);
+        self
+    }
+
+    /// Set the rasterizer state of the pass:
+    /// polygon mode with line width, face culling, front face, depth clamping and depth bias.
+    /// Overrides `PassDesc::rasterizer`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `rasterizer`: rasterizer state
+    pub fn set_rasterizer(&mut self, rasterizer: pso::Rasterizer) -> &mut Self {
+        self.rasterizer = rasterizer;
+        self
+    }
+
+    /// Set the primitive topology of the pass.
+    /// Overrides `PassDesc::primitive`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `primitive`: primitive topology
+    pub fn with_primitive(mut self, primitive: Primitive) -> Self {
+        self.set_primitive(primitive);
+        self
+    }
+
+    /// Set the primitive topology of the pass.
+    /// Overrides `PassDesc::primitive`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `primitive`: primitive topology
+    pub fn set_primitive(&mut self, primitive: Primitive) -> &mut Self {
+        self.primitive = primitive;
+        self
+    }
+
+    /// Set the policy of picking area the pass renders to.
+    /// Area passed to `Graph::draw_inline` is used by default.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `render_area`: render area policy
+    pub fn with_render_area(mut self, render_area: RenderArea) -> Self {
+        self.set_render_area(render_area);
+        self
+    }
+
+    /// Set the policy of picking area the pass renders to.
+    /// Area passed to `Graph::draw_inline` is used by default.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `render_area`: render area policy
+    pub fn set_render_area(&mut self, render_area: RenderArea) -> &mut Self {
+        self.render_area = render_area;
+        self
+    }
+
+    /// Set specialization constants for the shader stage of all pipeline variants.
+    /// Constants override ones with the same id set by `PassShaders`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `stage`: shader stage to specialize
+    /// - `constants`: specialization constants
+    pub fn with_specialization(
+        mut self,
+        stage: pso::Stage,
+        constants: &[pso::Specialization],
+    ) -> Self {
+        self.set_specialization(stage, constants);
+        self
+    }
+
+    /// Set specialization constants for the shader stage of all pipeline variants.
+    /// Constants override ones with the same id set by `PassShaders`.
+    /// Replaces constants previously set for the stage.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `stage`: shader stage to specialize
+    /// - `constants`: specialization constants
+    pub fn set_specialization(
+        &mut self,
+        stage: pso::Stage,
+        constants: &[pso::Specialization],
+    ) -> &mut Self {
+        self.specializations.retain(|&(s, _)| s != stage);
+        self.specializations.push((stage, constants.to_vec()));
         self
     }
 
@@ -221,12 +521,15 @@ where
     }
 
     /// Build the `PassNode` that will be added to the rendering `Graph`.
+    /// Graphics pipelines of the node are created later along with pipelines of other passes.
     pub(crate) fn build<B, E>(
         self,
         device: &B::Device,
         extent: Extent,
         attachments: &[AttachmentDesc],
         views: &[B::ImageView],
+        samplers: &[(image::SamplerInfo, B::Sampler)],
+        shared: &mut SharedObjects<B>,
         index: usize,
     ) -> Result<PassNode<B, P>, GraphBuildError<E>>
     where
@@ -237,12 +540,73 @@ where
 
         // Check attachments setup
         assert_eq!(self.sampled.len(), self.pass.sampled());
+        assert!(
+            self.pass.sampled_bindings().is_empty()
+                || self.pass.sampled_bindings().len() == self.pass.sampled()
+        );
+        assert!(
+            self.pass.sampler_bindings().is_empty()
+                || self.pass.sampler_bindings().len() == self.pass.sampled()
+        );
+        assert!(
+            self.pass.storage_bindings().is_empty()
+                || self.pass.storage_bindings().len() == self.pass.storage()
+        );
         assert_eq!(self.inputs.len(), self.pass.inputs());
         assert_eq!(self.colors.len(), self.pass.colors());
         assert_eq!(
             self.depth_stencil.is_some(),
             self.pass.depth() || self.pass.stencil()
         );
+        if let Some(pso::StencilTest::On { .. }) = self.stencil_test {
+            assert!(self.pass.stencil());
+        }
+        if let Some((depth_stencil, _)) = self.depth_stencil {
+            let format = attachments[depth_stencil.index()].format;
+            assert!(!self.pass.depth() || format.is_depth());
+            assert!(!self.pass.stencil() || format.is_stencil());
+        }
+
+        // Check pipeline variants
+        assert!(self.pass.variants() > 0);
+        for variant in 0..self.pass.variants() {
+            assert!(
+                self.pass.variant_blends(variant).is_empty()
+                    || self.pass.variant_blends(variant).len() == self.pass.colors()
+            );
+        }
+
+        // Resolve locations of vertex attributes for each variant
+        let locations = (0..self.pass.variants())
+            .map(|variant| attribute_locations(self.pass.variant_vertices(variant)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(GraphBuildError::VertexAttributeOverlap)?;
+
+        // Find images to copy while the pass is disabled
+        let forward = match self.forward {
+            Some((from, to)) => {
+                let readable = self.sampled.iter().any(|&(a, _)| a == from)
+                    || self.storages.iter().any(|&(a, _)| a == from);
+                let writable = self.colors.iter().any(|&(a, _)| a == to);
+                let (from, to) = (&attachments[from.index()], &attachments[to.index()]);
+                if !readable || !writable || to.is_surface || from.format != to.format {
+                    return Err(GraphBuildError::InvalidConfiguaration);
+                }
+                // Source is accessed by shaders of the pass
+                // and destination is left in the final layout of the renderpass
+                Some((
+                    (
+                        from.images.clone().ok_or(GraphBuildError::InvalidConfiguaration)?,
+                        from.shader_layout(),
+                    ),
+                    (
+                        to.images.clone().ok_or(GraphBuildError::InvalidConfiguaration)?,
+                        to.image_layout_transition(index).end,
+                    ),
+                ))
+            }
+            None => None,
+        };
 
         // Construct `RenderPass`
         // with single `Subpass` for now
@@ -288,90 +652,108 @@ where
                         load: depth_stencil.load_op(index),
                         store: depth_stencil.store_op(index),
                     },
-                    stencil_ops: pass::AttachmentOps::DONT_CARE,
+                    stencil_ops: pass::AttachmentOps {
+                        load: depth_stencil.stencil_load_op(index),
+                        store: depth_stencil.stencil_store_op(index),
+                    },
                     layouts: depth_stencil.image_layout_transition(index),
                 };
                 debug!("Init depth attachment {:?}", attachment);
                 attachment
             });
 
-            let depth_stencil_ref = depth_stencil.as_ref().map(|_| {
-                (
-                    inputs.len() + colors.len(),
-                    image::ImageLayout::DepthStencilAttachmentOptimal,
-                )
-            });
-
-            // Configure the only `Subpass` using all attachments
-            let subpass = pass::SubpassDesc {
-                colors: &(0..colors.len())
-                    .map(|i| (i + inputs.len(), image::ImageLayout::ColorAttachmentOptimal))
-                    .collect::<Vec<_>>(),
-                depth_stencil: depth_stencil_ref.as_ref(),
-                inputs: &(0..inputs.len())
-                    .map(|i| (i, image::ImageLayout::ShaderReadOnlyOptimal))
-                    .collect::<Vec<_>>(),
-                preserves: &[],
-            };
-
-            let renderpass = device.create_render_pass(
-                &inputs
-                    .chain(colors)
-                    .chain(depth_stencil)
-                    .collect::<Vec<_>>(),
-                &[subpass],
-                &[],
-            );
-
-            debug!("Randerpass: {:?}", renderpass);
-            renderpass
+            // Passes with identical attachments share the renderpass
+            let counts = (inputs.len(), colors.len());
+            shared.renderpass(
+                inputs.chain(colors).chain(depth_stencil).collect(),
+                counts,
+                self.depth_read_only,
+                device,
+            )
         };
 
-        let descriptors = DescriptorPool::new(&self.pass.bindings(), device);
-
-        let pipeline_layout = device.create_pipeline_layout(Some(descriptors.layout()), &[]);
-        debug!("Pipeline layout: {:?}", pipeline_layout);
-
-        let mut shaders = SmallVec::new();
-        let graphics_pipeline = {
-            // Init basic configuration
-            let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
-                self.pass.shaders(&mut shaders, device)?,
-                self.primitive,
-                self.rasterizer.clone(),
-                &pipeline_layout,
-                pass::Subpass {
-                    index: 0,
-                    main_pass: &renderpass,
-                },
-            );
-
-            // Default configuration for blending targets for all color targets
-            pipeline_desc.blender.targets =
-                (0..self.pass.colors()).map(|i| self.colors[i].1).collect();
+        // Create pool for each descriptor set declared by the pass
+        let descriptors = self.pass
+            .bindings()
+            .iter()
+            .map(|bindings| DescriptorPool::new(bindings, device))
+            .collect::<Vec<_>>();
 
-            // Default configuration for depth-stencil
-            pipeline_desc.depth_stencil = self.depth_stencil.map(|(_, desc)| desc);
+        // Find samplers created by the graph for sampled attachments
+        let sampler_indices = self.sampled
+            .iter()
+            .map(|&(_, ref info)| {
+                samplers
+                    .iter()
+                    .position(|&(ref sampler_info, _)| sampler_info == info)
+                    .expect("Graph creates samplers for all sampled attachments")
+            })
+            .collect::<Vec<_>>();
 
-            // Add all vertex descriptors
-            for &(attributes, stride) in self.pass.vertices() {
-                push_vertex_desc(attributes, stride, &mut pipeline_desc);
+        // Create descriptor sets for sampled and storage attachments managed by the graph
+        let bindings = attachment_bindings(&self.pass);
+        let sets = self.pass
+            .bindings()
+            .iter()
+            .map(|bindings| bindings.to_vec())
+            .chain(Some(bindings.clone()).filter(|bindings| !bindings.is_empty()))
+            .collect::<Vec<_>>();
+        let attachment_sets = {
+            if bindings.is_empty() {
+                None
+            } else {
+                let mut pool = DescriptorPool::new(&bindings, device);
+                let sets = write_attachment_sets(
+                    &self.sampled,
+                    &sampler_indices
+                        .iter()
+                        .map(|&index| &samplers[index].1)
+                        .collect::<Vec<_>>(),
+                    &self.storages,
+                    &self.pass,
+                    attachments,
+                    views,
+                    &mut pool,
+                    device,
+                )?;
+                debug!("Attachment descriptor sets: {:?}", sets);
+                Some((pool, sets))
             }
+        };
 
-            // Create `GraphicsPipeline`
-            let graphics_pipeline = device
-                .create_graphics_pipelines(&[pipeline_desc])
-                .pop()
-                .unwrap()?;
+        // Passes with identical bindings and push constants share the pipeline layout
+        let pipeline_layout = shared.pipeline_layout(
+            &sets,
+            self.pass.push_constants(),
+            descriptors
+                .iter()
+                .chain(attachment_sets.as_ref().map(|&(ref pool, _)| pool))
+                .map(DescriptorPool::layout),
+            device,
+        );
 
-            debug!("Graphics pipeline: {:?}", graphics_pipeline);
-            graphics_pipeline
+        // Remember everything required to recreate pipelines when shaders are reloaded
+        let settings = PipelineSettings {
+            primitive: self.primitive,
+            rasterizer: self.rasterizer.clone(),
+            blends: self.colors.iter().map(|&(_, blend)| blend).collect(),
+            depth_stencil: self.depth_stencil.map(|(_, desc)| {
+                let stencil = self.stencil_test.unwrap_or(desc.stencil);
+                pso::DepthStencilDesc {
+                    stencil: if self.depth_read_only {
+                        read_only_stencil(stencil)
+                    } else {
+                        stencil
+                    },
+                    ..desc
+                }
+            }),
+            locations,
+            specializations: self.specializations,
+            #[cfg(feature = "reflection")]
+            sets,
         };
 
-        for module in shaders {
-            device.destroy_shader_module(module);
-        }
-
         // This color will be set to targets that aren't get cleared
         let ignored_color = ClearValue::Color(ClearColor::Float([0.1, 0.2, 0.3, 1.0]));
         let ignored_depth = ClearValue::DepthStencil(ClearDepthStencil(1.0, 0));
@@ -395,6 +777,14 @@ where
 
         debug!("Clear values: {:?}", clears);
 
+        // Remember attachments in the same order to update clear values later
+        let clear_attachments = self.inputs
+            .iter()
+            .cloned()
+            .chain(self.colors.iter().map(|&(a, _)| a))
+            .chain(self.depth_stencil.as_ref().map(|&(a, _)| a))
+            .collect::<Vec<_>>();
+
         // create framebuffers
         let framebuffer: SuperFramebuffer<B> = {
             if self.inputs.len() == 0 && self.colors.len() == 1
@@ -442,6 +832,20 @@ where
             }
         };
 
+        // Storage attachments aren't synchronized by the renderpass
+        let storage_barriers = self.storages
+            .iter()
+            .map(|&(a, _)| {
+                let ref storage = attachments[a.index()];
+                let images = storage
+                    .images
+                    .clone()
+                    .ok_or(GraphBuildError::InvalidConfiguaration)?;
+                Ok((images, storage.storage_barrier_states(index)))
+            })
+            .collect::<Result<Vec<_>, GraphBuildError<E>>>()?;
+        debug!("Storage barriers: {:?}", storage_barriers);
+
         let inputs = {
             let mut frames = None;
             debug!(
@@ -450,7 +854,8 @@ where
             );
             for indices in self.sampled
                 .into_iter()
-                .chain(self.storages)
+                .map(|(a, _)| a)
+                .chain(self.storages.iter().map(|&(a, _)| a))
                 .chain(self.inputs)
                 .map(|a| attachments[a.0].images.clone())
             {
@@ -468,46 +873,360 @@ where
 
         Ok(PassNode {
             clears,
+            attachments: clear_attachments,
             descriptors,
+            attachment_sets,
+            samplers: sampler_indices,
             pipeline_layout,
-            graphics_pipeline,
+            graphics_pipelines: Vec::new(),
             renderpass,
             framebuffer,
             pass: self.pass,
             depends: None,
             inputs,
+            storage_barriers,
+            forward,
+            enabled: true,
+            render_area: self.render_area,
+            extent,
+            settings,
         })
     }
 }
 
-fn push_vertex_desc<B>(
-    attributes: &[pso::Element<Format>],
-    stride: pso::ElemStride,
+/// Push vertex buffers with their attributes at resolved `locations`.
+fn push_vertex_descs<B>(
+    vertices: &[VertexFormat],
+    locations: &[pso::Location],
     pipeline_desc: &mut pso::GraphicsPipelineDesc<B>,
 ) where
     B: Backend,
 {
-    let index = pipeline_desc.vertex_buffers.len() as pso::BufferIndex;
-
-    pipeline_desc
-        .vertex_buffers
-        .push(pso::VertexBufferDesc { stride, rate: 0 });
-
-    let mut location = pipeline_desc
-        .attributes
-        .last()
-        .map(|a| a.location + 1)
-        .unwrap_or(0);
-    for &attribute in attributes {
-        pipeline_desc.attributes.push(pso::AttributeDesc {
-            location,
-            binding: index,
-            element: attribute,
+    let mut locations = locations.iter();
+    for (index, format) in vertices.iter().enumerate() {
+        pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+            stride: format.stride,
+            rate: format.rate,
         });
-        location += 1;
+
+        for (attribute, &location) in format.attributes.iter().zip(&mut locations) {
+            pipeline_desc.attributes.push(pso::AttributeDesc {
+                location,
+                binding: index as pso::BufferIndex,
+                element: attribute.element,
+            });
+        }
+    }
+}
+
+/// Renderpasses and pipeline layouts shared between passes with identical descriptions.
+/// `PassNode`s hold references to shared objects and the last one destroys them.
+///
+/// Descriptor pools are not shared. `Pass::prepare` and `Pass::cleanup` get exclusive access
+/// to the pools of the pass and own the sets allocated from them, so sharing pools would
+/// require synchronization and changes to the `Pass` trait.
+/// Descriptor set layouts with identical bindings are compatible, so passes still share
+/// pipeline layouts created with the set layouts of the first pass.
+#[derive(Debug)]
+pub(crate) struct SharedObjects<B: Backend> {
+    renderpasses: Vec<(RenderPassKey, Arc<B::RenderPass>)>,
+    layouts: Vec<(LayoutKey, Arc<B::PipelineLayout>)>,
+}
+
+type PushConstant = (pso::ShaderStageFlags, Range<u32>);
+type RenderPassKey = (Vec<pass::Attachment>, (usize, usize), bool);
+type LayoutKey = (Vec<Vec<pso::DescriptorSetLayoutBinding>>, Vec<PushConstant>);
+
+impl<B> SharedObjects<B>
+where
+    B: Backend,
+{
+    /// Create empty storage.
+    pub(crate) fn new() -> Self {
+        SharedObjects {
+            renderpasses: Vec::new(),
+            layouts: Vec::new(),
+        }
+    }
+
+    /// Find or create renderpass with single subpass.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `attachments`: input attachments followed by color and depth-stencil attachments
+    /// - `counts`: number of input and color attachments
+    /// - `depth_read_only`: depth-stencil attachment is used in read-only layout
+    /// - `device`: graphics device
+    fn renderpass(
+        &mut self,
+        attachments: Vec<pass::Attachment>,
+        counts: (usize, usize),
+        depth_read_only: bool,
+        device: &B::Device,
+    ) -> Arc<B::RenderPass> {
+        let key = (attachments, counts, depth_read_only);
+        if let Some(&(_, ref renderpass)) = self.renderpasses.iter().find(|&&(ref k, _)| *k == key)
+        {
+            debug!("Share renderpass: {:?}", renderpass);
+            return renderpass.clone();
+        }
+
+        let (inputs, colors) = counts;
+        let depth_stencil_ref = if key.0.len() > inputs + colors {
+            let layout = if depth_read_only {
+                image::ImageLayout::DepthStencilReadOnlyOptimal
+            } else {
+                image::ImageLayout::DepthStencilAttachmentOptimal
+            };
+            Some((inputs + colors, layout))
+        } else {
+            None
+        };
+
+        // Configure the only `Subpass` using all attachments
+        let subpass = pass::SubpassDesc {
+            colors: &(0..colors)
+                .map(|i| (i + inputs, image::ImageLayout::ColorAttachmentOptimal))
+                .collect::<Vec<_>>(),
+            depth_stencil: depth_stencil_ref.as_ref(),
+            inputs: &(0..inputs)
+                .map(|i| (i, image::ImageLayout::ShaderReadOnlyOptimal))
+                .collect::<Vec<_>>(),
+            preserves: &[],
+        };
+
+        let renderpass = Arc::new(device.create_render_pass(&key.0, &[subpass], &[]));
+        debug!("Randerpass: {:?}", renderpass);
+        self.renderpasses.push((key, renderpass.clone()));
+        renderpass
+    }
+
+    /// Find or create pipeline layout.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `sets`: bindings of all descriptor sets
+    /// - `push_constants`: push constant ranges
+    /// - `layouts`: descriptor set layouts created from `sets`
+    /// - `device`: graphics device
+    fn pipeline_layout<'a, I>(
+        &mut self,
+        sets: &[Vec<pso::DescriptorSetLayoutBinding>],
+        push_constants: &[PushConstant],
+        layouts: I,
+        device: &B::Device,
+    ) -> Arc<B::PipelineLayout>
+    where
+        I: IntoIterator<Item = &'a B::DescriptorSetLayout>,
+    {
+        if let Some(&(_, ref layout)) = self.layouts
+            .iter()
+            .find(|&&((ref s, ref p), _)| s[..] == sets[..] && p[..] == push_constants[..])
+        {
+            debug!("Share pipeline layout: {:?}", layout);
+            return layout.clone();
+        }
+
+        let layout = Arc::new(device.create_pipeline_layout(layouts, push_constants));
+        debug!("Pipeline layout: {:?}", layout);
+        self.layouts
+            .push(((sets.to_vec(), push_constants.to_vec()), layout.clone()));
+        layout
     }
 }
 
+/// Settings required to create graphics pipelines of the pass.
+/// Kept by `PassNode` to recreate pipelines when shaders are reloaded.
+#[derive(Debug)]
+pub(crate) struct PipelineSettings {
+    primitive: Primitive,
+    rasterizer: pso::Rasterizer,
+    blends: Vec<pso::ColorBlendDesc>,
+    depth_stencil: Option<pso::DepthStencilDesc>,
+    locations: Vec<Vec<pso::Location>>,
+    specializations: Vec<(pso::Stage, Vec<pso::Specialization>)>,
+    #[cfg(feature = "reflection")]
+    sets: Vec<Vec<pso::DescriptorSetLayoutBinding>>,
+}
+
+impl PipelineSettings {
+    /// Create empty storage for shader modules of each variant of the pass.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `pass`: pass to load shaders for
+    /// - `cache`: shader modules shared between passes
+    pub(crate) fn shader_modules<B, P>(
+        &self,
+        pass: &P,
+        cache: &ShaderCache<B>,
+    ) -> Vec<ShaderModules<B>>
+    where
+        B: Backend,
+        P: PassDesc,
+    {
+        (0..pass.variants())
+            .map(|variant| self.variant_shader_modules(pass, variant, cache))
+            .collect()
+    }
+
+    /// Shader modules of each variant are checked against the layout of the pass.
+    #[cfg(feature = "reflection")]
+    fn variant_shader_modules<B, P>(
+        &self,
+        pass: &P,
+        variant: usize,
+        cache: &ShaderCache<B>,
+    ) -> ShaderModules<B>
+    where
+        B: Backend,
+        P: PassDesc,
+    {
+        let mut modules = cache.modules();
+        modules.set_layout(ShaderInterface {
+            stages: pso::ShaderStageFlags::empty(),
+            sets: self.sets.clone(),
+            inputs: self.locations[variant].clone(),
+            push_constants: pass.push_constants().to_vec(),
+        });
+        modules
+    }
+
+    #[cfg(not(feature = "reflection"))]
+    fn variant_shader_modules<B, P>(
+        &self,
+        _pass: &P,
+        _variant: usize,
+        cache: &ShaderCache<B>,
+    ) -> ShaderModules<B>
+    where
+        B: Backend,
+        P: PassDesc,
+    {
+        cache.modules()
+    }
+
+    /// Load shaders and fill descriptions of graphics pipelines for all variants of the pass.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `pass`: pass to load shaders from
+    /// - `shaders`: storage for shader modules of each variant
+    /// - `specializations`: storage for specialization constants of each variant
+    /// - `layout`: pipeline layout of the pass
+    /// - `renderpass`: renderpass of the pass
+    /// - `device`: graphics device
+    pub(crate) fn pipeline_descs<'a, B, P>(
+        &self,
+        pass: &'a P,
+        shaders: &'a mut [ShaderModules<B>],
+        specializations: &'a mut Vec<Vec<(pso::Stage, Vec<pso::Specialization>)>>,
+        layout: &'a B::PipelineLayout,
+        renderpass: &'a B::RenderPass,
+        device: &B::Device,
+    ) -> Result<Vec<pso::GraphicsPipelineDesc<'a, B>>, pso::CreationError>
+    where
+        B: Backend,
+        P: PassShaders<B>,
+    {
+        let mut sets = Vec::new();
+        for (variant, shaders) in shaders.iter_mut().enumerate() {
+            sets.push(pass.variant_shaders(variant, shaders, device)
+                .map_err(pso::CreationError::Shader)?);
+        }
+
+        // Merge specialization constants from the builder with those set by the pass
+        *specializations = sets.iter_mut()
+            .map(|set| {
+                self.specializations
+                    .iter()
+                    .filter_map(|&(stage, ref constants)| match entry_point(set, stage) {
+                        Some(entry) => Some((stage, specialize(entry.specialization, constants))),
+                        None => {
+                            warn!("Pass {} has no {:?} shader to specialize", pass.name(), stage);
+                            None
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let specializations: &'a Vec<_> = specializations;
+
+        let mut pipeline_descs = Vec::new();
+        for (variant, mut set) in sets.into_iter().enumerate() {
+            for &(stage, ref constants) in &specializations[variant] {
+                if let Some(entry) = entry_point(&mut set, stage) {
+                    entry.specialization = constants;
+                }
+            }
+
+            // Init basic configuration
+            let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+                set,
+                self.primitive,
+                self.rasterizer.clone(),
+                layout,
+                pass::Subpass {
+                    index: 0,
+                    main_pass: renderpass,
+                },
+            );
+
+            // Blending targets for all color targets from the variant or the builder
+            pipeline_desc.blender.targets = if pass.variant_blends(variant).is_empty() {
+                self.blends.clone()
+            } else {
+                pass.variant_blends(variant).to_vec()
+            };
+
+            // Configuration for depth-stencil
+            pipeline_desc.depth_stencil = self.depth_stencil;
+
+            // Add all vertex descriptors
+            push_vertex_descs(
+                pass.variant_vertices(variant),
+                &self.locations[variant],
+                &mut pipeline_desc,
+            );
+
+            pipeline_descs.push(pipeline_desc);
+        }
+
+        Ok(pipeline_descs)
+    }
+}
+
+/// Get entry point of the shader set for the stage.
+fn entry_point<'a, 'b, B>(
+    set: &'b mut pso::GraphicsShaderSet<'a, B>,
+    stage: pso::Stage,
+) -> Option<&'b mut pso::EntryPoint<'a, B>>
+where
+    B: Backend,
+{
+    match stage {
+        pso::Stage::Vertex => Some(&mut set.vertex),
+        pso::Stage::Hull => set.hull.as_mut(),
+        pso::Stage::Domain => set.domain.as_mut(),
+        pso::Stage::Geometry => set.geometry.as_mut(),
+        pso::Stage::Fragment => set.fragment.as_mut(),
+        pso::Stage::Compute => None,
+    }
+}
+
+/// Replace specialization constants with ones that have the same id and add the rest.
+fn specialize(
+    specialization: &[pso::Specialization],
+    constants: &[pso::Specialization],
+) -> Vec<pso::Specialization> {
+    specialization
+        .iter()
+        .filter(|s| constants.iter().all(|c| c.id != s.id))
+        .chain(constants)
+        .cloned()
+        .collect()
+}
+
 fn depth_stencil_desc<P>(pass: &P) -> pso::DepthStencilDesc
 where
     P: PassDesc,
@@ -525,3 +1244,141 @@ where
         stencil: pso::StencilTest::Off,
     }
 }
+
+/// Stencil test that never writes to the attachment used in read-only layout.
+fn read_only_stencil(stencil: pso::StencilTest) -> pso::StencilTest {
+    let read_only = |face: pso::StencilFaceState| pso::StencilFaceState {
+        mask_write: 0,
+        op_fail: pso::StencilOp::Keep,
+        op_depth_fail: pso::StencilOp::Keep,
+        op_pass: pso::StencilOp::Keep,
+        ..face
+    };
+    match stencil {
+        pso::StencilTest::On { front, back } => pso::StencilTest::On {
+            front: read_only(front),
+            back: read_only(back),
+        },
+        pso::StencilTest::Off => pso::StencilTest::Off,
+    }
+}
+
+/// Collect bindings of the descriptor set managed by the graph.
+fn attachment_bindings<P>(pass: &P) -> Vec<pso::DescriptorSetLayoutBinding>
+where
+    P: PassDesc,
+{
+    let sampled = pass.sampled_bindings()
+        .iter()
+        .map(|&binding| (binding, pso::DescriptorType::SampledImage));
+    let samplers = pass.sampler_bindings()
+        .iter()
+        .map(|&binding| (binding, pso::DescriptorType::Sampler));
+    let storages = pass.storage_bindings()
+        .iter()
+        .map(|&binding| (binding, pso::DescriptorType::StorageImage));
+    let stage_flags = pass.attachment_stages();
+
+    sampled
+        .chain(samplers)
+        .chain(storages)
+        .map(|(binding, ty)| pso::DescriptorSetLayoutBinding {
+            binding,
+            ty,
+            count: 1,
+            stage_flags,
+        })
+        .collect()
+}
+
+/// Allocate descriptor set for each frame and write views of sampled and storage attachments
+/// along with samplers.
+fn write_attachment_sets<B, P, E>(
+    sampled: &[(AttachmentRef, image::SamplerInfo)],
+    samplers: &[&B::Sampler],
+    storages: &[(AttachmentRef, bool)],
+    pass: &P,
+    attachments: &[AttachmentDesc],
+    views: &[B::ImageView],
+    pool: &mut DescriptorPool<B>,
+    device: &B::Device,
+) -> Result<Vec<B::DescriptorSet>, GraphBuildError<E>>
+where
+    B: Backend,
+    P: PassDesc,
+{
+    let sampled = sampled
+        .iter()
+        .zip(pass.sampled_bindings())
+        .map(|(&(ref a, _), &binding)| (a, binding, pso::DescriptorType::SampledImage));
+    let storages = storages
+        .iter()
+        .zip(pass.storage_bindings())
+        .map(|(&(ref a, _), &binding)| (a, binding, pso::DescriptorType::StorageImage));
+
+    let mut frames = None;
+    let mut targets = Vec::new();
+    for (a, binding, ty) in sampled.chain(storages) {
+        let ref attachment = attachments[a.index()];
+        let indices = attachment
+            .views
+            .clone()
+            .ok_or(GraphBuildError::InvalidConfiguaration)?;
+        assert_eq!(*frames.get_or_insert(indices.len()), indices.len());
+        targets.push((indices.start, binding, ty, attachment.shader_layout()));
+    }
+
+    // Samplers are the same for all frames
+    let samplers = samplers
+        .iter()
+        .zip(pass.sampler_bindings())
+        .map(|(&sampler, &binding)| (binding, [sampler]))
+        .collect::<Vec<_>>();
+
+    Ok((0..frames.unwrap_or(0))
+        .map(|frame| {
+            let set = pool.allocate(device);
+            {
+                // Attachments are transitioned to the layout shaders access them in
+                // before the pass starts.
+                let images = targets
+                    .iter()
+                    .map(|&(start, binding, ty, layout)| {
+                        (binding, ty, [(&views[start + frame], layout)])
+                    })
+                    .collect::<Vec<_>>();
+
+                let writes = images
+                    .iter()
+                    .map(|&(binding, ty, ref image)| pso::DescriptorSetWrite {
+                        set: &set,
+                        binding,
+                        array_offset: 0,
+                        write: match ty {
+                            pso::DescriptorType::SampledImage => {
+                                pso::DescriptorWrite::SampledImage(image)
+                            }
+                            _ => pso::DescriptorWrite::StorageImage(image),
+                        },
+                    })
+                    .chain(samplers.iter().map(|&(binding, ref sampler)| {
+                        pso::DescriptorSetWrite {
+                            set: &set,
+                            binding,
+                            array_offset: 0,
+                            write: pso::DescriptorWrite::Sampler(sampler),
+                        }
+                    }))
+                    .collect::<Vec<_>>();
+
+                device.write_descriptor_sets(&writes);
+            }
+            set
+        })
+        .collect())
+}
+
+/// Sampler used for attachments added without explicit sampler description.
+fn default_sampler_info() -> image::SamplerInfo {
+    image::SamplerInfo::new(image::FilterMethod::Bilinear, image::WrapMode::Clamp)
+}
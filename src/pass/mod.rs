@@ -1,23 +1,39 @@
 //! Defines the `Pass` trait, the main building block of the rendering `Graph`s
 
-pub use self::build::PassBuilder;
+pub use self::build::{PassBuilder, PassRef};
+pub(crate) use self::build::SharedObjects;
+use self::build::PipelineSettings;
 
 use std::borrow::Borrow;
 use std::fmt::Debug;
-use std::ops::{Deref, DerefMut};
-
-use gfx_hal::{Backend, Device};
-use gfx_hal::command::{ClearValue, CommandBuffer, Primary, Rect, RenderPassInlineEncoder};
-use gfx_hal::device::ShaderError;
-use gfx_hal::format::Format;
-use gfx_hal::pso::{DescriptorSetLayoutBinding, ElemStride, Element, GraphicsShaderSet,
-                   PipelineStage};
+use std::mem::replace;
+use std::ops::{Deref, DerefMut, Range};
+use std::sync::Arc;
+
+use gfx_hal::{Backend, Device, Primitive};
+use gfx_hal::command::{ClearValue, CommandBuffer, ImageCopy, OneShot, Primary, Rect,
+                       RenderPassInlineEncoder, Secondary, Submit, Viewport};
+use gfx_hal::device::{Extent, ShaderError};
+use gfx_hal::format::Aspects;
+use gfx_hal::image::{Access, ImageLayout, Offset, State, SubresourceLayers,
+                     SubresourceRange};
+use gfx_hal::memory::{Barrier, Dependencies};
+use gfx_hal::pass::Subpass;
+use gfx_hal::pool::CommandPool;
+use gfx_hal::pso::{ColorBlendDesc, CreationError, DescriptorBinding, DescriptorSetLayoutBinding,
+                   GraphicsPipelineDesc, GraphicsShaderSet, PipelineStage, Rasterizer,
+                   ShaderStageFlags, Specialization, Stage};
 use gfx_hal::queue::capability::{Graphics, Supports, Transfer};
 
+use rayon::current_num_threads;
+use rayon::prelude::*;
 use smallvec::SmallVec;
 
+use attachment::AttachmentRef;
 use descriptors::DescriptorPool;
 use frame::{pick, SuperFrame, SuperFramebuffer};
+use shaders::{ShaderCache, ShaderModules};
+use vertex::VertexFormat;
 
 mod build;
 
@@ -50,11 +66,83 @@ pub trait PassDesc: Debug {
     /// Will the pass use the stencil buffer
     fn stencil(&self) -> bool;
 
-    /// Vertex formats required by the pass
-    fn vertices(&self) -> &[(&[Element<Format>], ElemStride)];
+    /// Vertex formats required by the pass.
+    /// Each format describes a single vertex buffer.
+    fn vertices(&self) -> &[VertexFormat];
+
+    /// Bindings for each descriptor set used by the pass.
+    /// Separate `DescriptorPool` is created for every set
+    /// and pipeline layout is built from all of them in the same order.
+    fn bindings(&self) -> &[&[DescriptorSetLayoutBinding]];
+
+    /// Bindings for sampled attachments in the descriptor set managed by the `Graph`.
+    /// This set is bound right after sets from `PassDesc::bindings`.
+    ///
+    /// Must be either empty, in which case the pass writes descriptors for sampled images itself,
+    /// or contain one binding for each sampled attachment.
+    fn sampled_bindings(&self) -> &[DescriptorBinding] {
+        &[]
+    }
+
+    /// Bindings for samplers of sampled attachments in the descriptor set managed by the `Graph`.
+    /// This set is bound right after sets from `PassDesc::bindings`.
+    ///
+    /// Must be either empty or contain one binding for each sampled attachment.
+    fn sampler_bindings(&self) -> &[DescriptorBinding] {
+        &[]
+    }
 
-    /// Bindings for the descriptor sets used by the pass
-    fn bindings(&self) -> &[DescriptorSetLayoutBinding];
+    /// Bindings for storage attachments in the descriptor set managed by the `Graph`.
+    /// This set is bound right after sets from `PassDesc::bindings`.
+    ///
+    /// Must be either empty, in which case the pass writes descriptors for storage images itself,
+    /// or contain one binding for each storage attachment.
+    fn storage_bindings(&self) -> &[DescriptorBinding] {
+        &[]
+    }
+
+    /// Shader stages that access bindings of the descriptor set managed by the `Graph`.
+    fn attachment_stages(&self) -> ShaderStageFlags {
+        ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT
+    }
+
+    /// Push constant ranges used by the pass.
+    /// Pipeline layout is created with these ranges.
+    fn push_constants(&self) -> &[(ShaderStageFlags, Range<u32>)] {
+        &[]
+    }
+
+    /// Number of pipeline variants of the pass.
+    /// All variants share renderpass and pipeline layout of the pass
+    /// and can be selected while drawing.
+    fn variants(&self) -> usize {
+        1
+    }
+
+    /// Vertex formats required by the pipeline variant.
+    fn variant_vertices(&self, _variant: usize) -> &[VertexFormat] {
+        self.vertices()
+    }
+
+    /// Blending of color attachments for the pipeline variant.
+    ///
+    /// Must be either empty, in which case blending set in `PassBuilder` is used,
+    /// or contain one description for each color attachment.
+    fn variant_blends(&self, _variant: usize) -> &[ColorBlendDesc] {
+        &[]
+    }
+
+    /// Rasterizer state used by the pass.
+    /// `PassBuilder` starts from this state and falls back to `Rasterizer::FILL` if `None`.
+    fn rasterizer(&self) -> Option<Rasterizer> {
+        None
+    }
+
+    /// Primitive topology used by the pass.
+    /// `PassBuilder` starts from this topology and falls back to `TriangleList` if `None`.
+    fn primitive(&self) -> Option<Primitive> {
+        None
+    }
 
     /// Create builder
     fn build(self) -> PassBuilder<Self>
@@ -91,12 +179,42 @@ where
     fn stencil(&self) -> bool {
         P::stencil(self)
     }
-    fn vertices(&self) -> &[(&[Element<Format>], ElemStride)] {
+    fn vertices(&self) -> &[VertexFormat] {
         P::vertices(self)
     }
-    fn bindings(&self) -> &[DescriptorSetLayoutBinding] {
+    fn bindings(&self) -> &[&[DescriptorSetLayoutBinding]] {
         P::bindings(self)
     }
+    fn sampled_bindings(&self) -> &[DescriptorBinding] {
+        P::sampled_bindings(self)
+    }
+    fn sampler_bindings(&self) -> &[DescriptorBinding] {
+        P::sampler_bindings(self)
+    }
+    fn storage_bindings(&self) -> &[DescriptorBinding] {
+        P::storage_bindings(self)
+    }
+    fn attachment_stages(&self) -> ShaderStageFlags {
+        P::attachment_stages(self)
+    }
+    fn push_constants(&self) -> &[(ShaderStageFlags, Range<u32>)] {
+        P::push_constants(self)
+    }
+    fn variants(&self) -> usize {
+        P::variants(self)
+    }
+    fn variant_vertices(&self, variant: usize) -> &[VertexFormat] {
+        P::variant_vertices(self, variant)
+    }
+    fn variant_blends(&self, variant: usize) -> &[ColorBlendDesc] {
+        P::variant_blends(self, variant)
+    }
+    fn rasterizer(&self) -> Option<Rasterizer> {
+        P::rasterizer(self)
+    }
+    fn primitive(&self) -> Option<Primitive> {
+        P::primitive(self)
+    }
 }
 
 /// Trait to load shaders for `Pass`.
@@ -111,9 +229,9 @@ where
     ///
     /// ### Parameters
     ///
-    /// - `shaders`: `ShaderModule` objects created by the pass can be added here, if they are
-    ///               not stored in the Pass. If they are added here, they will be destroyed by
-    ///               the `Graph` after having been uploaded to the graphics device.
+    /// - `shaders`: `ShaderModule` objects can be created here with `ShaderModules::create`,
+    ///               if they are not stored in the Pass. If they are created here, they will be
+    ///               destroyed by the `Graph` after having been uploaded to the graphics device.
     /// - `device`: graphics device
     ///
     /// ### Returns
@@ -121,9 +239,27 @@ where
     /// A set of `EntryPoint`s for the pass shaders.
     fn shaders<'a>(
         &'a self,
-        shaders: &'a mut SmallVec<[B::ShaderModule; 5]>,
+        shaders: &'a mut ShaderModules<B>,
         device: &B::Device,
     ) -> Result<GraphicsShaderSet<'a, B>, ShaderError>;
+
+    /// Load shaders for the pipeline variant.
+    /// Loads shaders with `PassShaders::shaders` by default.
+    ///
+    /// ### Parameters
+    ///
+    /// - `variant`: index of the variant in range `0 .. PassDesc::variants`
+    /// - `shaders`: `ShaderModule` objects created by the pass can be added here, same as for
+    ///               `PassShaders::shaders`
+    /// - `device`: graphics device
+    fn variant_shaders<'a>(
+        &'a self,
+        _variant: usize,
+        shaders: &'a mut ShaderModules<B>,
+        device: &B::Device,
+    ) -> Result<GraphicsShaderSet<'a, B>, ShaderError> {
+        self.shaders(shaders, device)
+    }
 }
 
 impl<B, P, Y> PassShaders<B> for Y
@@ -134,11 +270,20 @@ where
 {
     fn shaders<'a>(
         &'a self,
-        shaders: &'a mut SmallVec<[B::ShaderModule; 5]>,
+        shaders: &'a mut ShaderModules<B>,
         device: &B::Device,
     ) -> Result<GraphicsShaderSet<'a, B>, ShaderError> {
         P::shaders(self, shaders, device)
     }
+
+    fn variant_shaders<'a>(
+        &'a self,
+        variant: usize,
+        shaders: &'a mut ShaderModules<B>,
+        device: &B::Device,
+    ) -> Result<GraphicsShaderSet<'a, B>, ShaderError> {
+        P::variant_shaders(self, variant, shaders, device)
+    }
 }
 
 /// `Pass`es are the building blocks a rendering `Graph`.
@@ -166,9 +311,12 @@ where
     ///
     /// ### Parameters:
     ///
-    /// - `pool`: descriptor pool to use
+    /// - `pools`: descriptor pools to use, one for each set from `PassDesc::bindings`
     /// - `cbuf`: command buffer to record commands to
     /// - `device`: graphics device
+    /// - `inputs`: images of sampled, storage and input attachments
+    /// - `samplers`: samplers for each sampled attachment
+    /// - `frame`: index of the frame
     /// - `aux`: auxiliary data
     ///
     /// ### Type parameters:
@@ -176,10 +324,11 @@ where
     /// - `C`: Hal `Capability`
     fn prepare<'a>(
         &mut self,
-        pool: &mut DescriptorPool<B>,
+        pools: &mut [DescriptorPool<B>],
         cbuf: &mut CommandBuffer<B, Transfer>,
         device: &B::Device,
         inputs: &[&B::Image],
+        samplers: &[&B::Sampler],
         frame: usize,
         aux: &mut T,
     );
@@ -192,28 +341,99 @@ where
     /// ### Parameters:
     ///
     /// - `layout`: pipeline layout
+    /// - `pipelines`: pipeline for each variant of the pass, first one is already bound
     /// - `encoder`: encoder used to record drawing commands
     /// - `device`: graphics device
+    /// - `inputs`: images of sampled, storage and input attachments
+    /// - `samplers`: samplers for each sampled attachment
+    /// - `frame`: index of the frame
     /// - `aux`: auxiliary data
     fn draw_inline<'a>(
         &mut self,
         layout: &B::PipelineLayout,
+        pipelines: &[B::GraphicsPipeline],
         encoder: RenderPassInlineEncoder<B, Primary>,
         device: &B::Device,
         inputs: &[&B::Image],
+        samplers: &[&B::Sampler],
         frame: usize,
         aux: &T,
     );
 
+    /// Number of chunks the drawing commands of this pass can be split into.
+    /// Used by `Graph::draw_parallel` to record chunks on different threads.
+    /// Passes that return `0` (default) are recorded with `Pass::draw_inline`
+    /// into the primary command buffer.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `frame`: index of the frame
+    /// - `aux`: auxiliary data
+    fn chunks(&self, _frame: usize, _aux: &T) -> usize {
+        0
+    }
+
+    /// Record drawing commands of a single chunk into a secondary command buffer.
+    ///
+    /// Called by `Graph::draw_parallel` instead of `Pass::draw_inline`
+    /// for passes that return non-zero `Pass::chunks`, which must override this method.
+    /// Default implementation records nothing and reports the missing override.
+    /// Chunks of the same pass may be recorded concurrently, so `T` and the pass itself are
+    /// shared. Pipeline of the first variant, descriptor set with attachments, viewport and
+    /// scissor are already set.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `layout`: pipeline layout
+    /// - `pipelines`: pipeline for each variant of the pass, first one is already bound
+    /// - `encoder`: encoder used to record drawing commands
+    /// - `device`: graphics device
+    /// - `inputs`: images of sampled, storage and input attachments
+    /// - `samplers`: samplers for each sampled attachment
+    /// - `chunk`: index of the chunk in range `0 .. Pass::chunks`
+    /// - `frame`: index of the frame
+    /// - `aux`: auxiliary data
+    fn draw_chunk<'a>(
+        &self,
+        _layout: &B::PipelineLayout,
+        _pipelines: &[B::GraphicsPipeline],
+        _encoder: RenderPassInlineEncoder<B, Secondary>,
+        _device: &B::Device,
+        _inputs: &[&B::Image],
+        _samplers: &[&B::Sampler],
+        chunk: usize,
+        _frame: usize,
+        _aux: &T,
+    ) {
+        if chunk == 0 {
+            error!(
+                "Pass {} splits drawing into chunks but doesn't implement `Pass::draw_chunk`",
+                self.name()
+            );
+        }
+        debug_assert!(false, "`Pass::draw_chunk` must be implemented with `Pass::chunks`");
+    }
+
+    /// Area to render to when the pass uses `RenderArea::Dynamic`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `viewport`: area passed to `Graph::draw_inline`
+    /// - `frame`: index of the frame
+    /// - `aux`: auxiliary data
+    fn render_area(&self, viewport: Rect, _frame: usize, _aux: &T) -> Rect {
+        viewport
+    }
+
     /// Cleanup before dropping this pass
     ///
     /// ### Parameters:
     ///
-    /// - `pool`: descriptor pool used for this pass in the rendering graph
+    /// - `pools`: descriptor pools used for this pass in the rendering graph
     /// - `device`: graphics device
     /// - `aux`: Auxiliary pass data, if the pass have anything stored there that needs to be
     ///          disposed
-    fn cleanup(&mut self, pool: &mut DescriptorPool<B>, device: &B::Device, aux: &mut T);
+    fn cleanup(&mut self, pools: &mut [DescriptorPool<B>], device: &B::Device, aux: &mut T);
 }
 
 impl<B, P, T, Y> Pass<B, T> for Y
@@ -224,30 +444,97 @@ where
 {
     fn prepare<'a>(
         &mut self,
-        pool: &mut DescriptorPool<B>,
+        pools: &mut [DescriptorPool<B>],
         cbuf: &mut CommandBuffer<B, Transfer>,
         device: &B::Device,
         inputs: &[&B::Image],
+        samplers: &[&B::Sampler],
         frame: usize,
         aux: &mut T,
     ) {
-        P::prepare(self, pool, cbuf, device, inputs, frame, aux)
+        P::prepare(self, pools, cbuf, device, inputs, samplers, frame, aux)
     }
 
     fn draw_inline<'a>(
         &mut self,
         layout: &B::PipelineLayout,
+        pipelines: &[B::GraphicsPipeline],
         encoder: RenderPassInlineEncoder<B, Primary>,
         device: &B::Device,
         inputs: &[&B::Image],
+        samplers: &[&B::Sampler],
         frame: usize,
         aux: &T,
     ) {
-        P::draw_inline(self, layout, encoder, device, inputs, frame, aux)
+        P::draw_inline(
+            self,
+            layout,
+            pipelines,
+            encoder,
+            device,
+            inputs,
+            samplers,
+            frame,
+            aux,
+        )
+    }
+
+    fn chunks(&self, frame: usize, aux: &T) -> usize {
+        P::chunks(self, frame, aux)
+    }
+
+    fn draw_chunk<'a>(
+        &self,
+        layout: &B::PipelineLayout,
+        pipelines: &[B::GraphicsPipeline],
+        encoder: RenderPassInlineEncoder<B, Secondary>,
+        device: &B::Device,
+        inputs: &[&B::Image],
+        samplers: &[&B::Sampler],
+        chunk: usize,
+        frame: usize,
+        aux: &T,
+    ) {
+        P::draw_chunk(
+            self,
+            layout,
+            pipelines,
+            encoder,
+            device,
+            inputs,
+            samplers,
+            chunk,
+            frame,
+            aux,
+        )
+    }
+
+    fn render_area(&self, viewport: Rect, frame: usize, aux: &T) -> Rect {
+        P::render_area(self, viewport, frame, aux)
     }
 
-    fn cleanup(&mut self, pool: &mut DescriptorPool<B>, device: &B::Device, aux: &mut T) {
-        P::cleanup(self, pool, device, aux)
+    fn cleanup(&mut self, pools: &mut [DescriptorPool<B>], device: &B::Device, aux: &mut T) {
+        P::cleanup(self, pools, device, aux)
+    }
+}
+
+/// Policy of picking the area the pass renders to.
+/// Viewport, scissor and render area of the renderpass are all set to this area.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderArea {
+    /// Area passed to `Graph::draw_inline`.
+    Viewport,
+    /// Whole attachments of the pass.
+    Full,
+    /// Fixed area.
+    Fixed(Rect),
+    /// Area returned by `Pass::render_area`.
+    Dynamic,
+}
+
+impl Default for RenderArea {
+    fn default() -> Self {
+        RenderArea::Viewport
     }
 }
 
@@ -262,13 +549,22 @@ where
 #[derive(Debug)]
 pub(crate) struct PassNode<B: Backend, P> {
     clears: Vec<ClearValue>,
-    descriptors: DescriptorPool<B>,
-    pipeline_layout: B::PipelineLayout,
-    graphics_pipeline: B::GraphicsPipeline,
-    renderpass: B::RenderPass,
+    attachments: Vec<AttachmentRef>,
+    descriptors: Vec<DescriptorPool<B>>,
+    attachment_sets: Option<(DescriptorPool<B>, Vec<B::DescriptorSet>)>,
+    samplers: Vec<usize>,
+    pipeline_layout: Arc<B::PipelineLayout>,
+    graphics_pipelines: Vec<B::GraphicsPipeline>,
+    renderpass: Arc<B::RenderPass>,
     framebuffer: SuperFramebuffer<B>,
     pass: P,
     inputs: Vec<Vec<usize>>,
+    storage_barriers: Vec<(Range<usize>, Range<State>)>,
+    forward: Option<((Range<usize>, ImageLayout), (Range<usize>, ImageLayout))>,
+    enabled: bool,
+    render_area: RenderArea,
+    extent: Extent,
+    settings: PipelineSettings,
     pub(crate) depends: Option<(usize, PipelineStage)>,
 }
 
@@ -285,6 +581,8 @@ where
     ///
     /// - `cbuf`: command buffer to record transfer commands to
     /// - `device`: graphics device
+    /// - `images`: all images created by the graph
+    /// - `samplers`: all samplers created by the graph
     /// - `aux`: auxiliary data for the inner `Pass`
     ///
     /// ### Type parameters:
@@ -295,6 +593,7 @@ where
         cbuf: &mut CommandBuffer<B, C>,
         device: &B::Device,
         images: &[I],
+        samplers: &[B::Sampler],
         frame: SuperFrame<B>,
         aux: &mut T,
     ) where
@@ -302,14 +601,8 @@ where
         P: Pass<B, T>,
         I: Borrow<B::Image>,
     {
-        let inputs = self.inputs
-            .get(frame.index())
-            .map_or(SmallVec::new(), |inputs| {
-                inputs
-                    .iter()
-                    .map(|&index| images[index].borrow())
-                    .collect::<SmallVec<[_; 16]>>()
-            });
+        let inputs = self.pick_inputs(images, frame);
+        let samplers = self.pick_samplers(samplers);
 
         // Run custom preparation
         // * Write descriptor sets
@@ -320,6 +613,7 @@ where
             cbuf.downgrade(),
             device,
             &inputs,
+            &samplers,
             frame.index(),
             aux,
         );
@@ -331,6 +625,8 @@ where
     /// ### Parameters:
     ///
     /// - `cbuf`: command buffer to record commands to
+    /// - `images`: all images created by the graph
+    /// - `samplers`: all samplers created by the graph
     /// - `rect`: area to draw in
     /// - `frame`: specifies which framebuffer and descriptor sets to use
     /// - `device`: graphics device
@@ -344,6 +640,7 @@ where
         cbuf: &mut CommandBuffer<B, C>,
         device: &B::Device,
         images: &[I],
+        samplers: &[B::Sampler],
         rect: Rect,
         frame: SuperFrame<B>,
         aux: &T,
@@ -353,7 +650,16 @@ where
         I: Borrow<B::Image>,
     {
         // Bind pipeline
-        cbuf.bind_graphics_pipeline(&self.graphics_pipeline);
+        cbuf.bind_graphics_pipeline(&self.graphics_pipelines[0]);
+
+        // Bind descriptor set with attachments right after sets of the pass
+        if let Some((_, ref sets)) = self.attachment_sets {
+            cbuf.bind_graphics_descriptor_sets(
+                &self.pipeline_layout,
+                self.descriptors.len(),
+                Some(&sets[frame.index()]),
+            );
+        }
 
         let encoder = {
             // Begin render pass with single inline subpass
@@ -365,26 +671,439 @@ where
             )
         };
 
-        let inputs = self.inputs
-            .get(frame.index())
-            .map_or(SmallVec::new(), |inputs| {
-                inputs
-                    .iter()
-                    .map(|&index| images[index].borrow())
-                    .collect::<SmallVec<[_; 16]>>()
-            });
+        let inputs = self.pick_inputs(images, frame);
+        let samplers = self.pick_samplers(samplers);
 
         // Record custom drawing calls
         self.pass.draw_inline(
             &self.pipeline_layout,
+            &self.graphics_pipelines,
             encoder,
             device,
             &inputs,
+            &samplers,
             frame.index(),
             aux,
         );
     }
 
+    /// Name of the inner `Pass`.
+    pub fn name(&self) -> &str
+    where
+        P: PassDesc,
+    {
+        self.pass.name()
+    }
+
+    /// Pick viewport for the pass according to its `RenderArea`.
+    /// Depth range is taken from `viewport`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `viewport`: viewport passed to the graph
+    /// - `frame`: index of the frame
+    /// - `aux`: auxiliary data for the inner `Pass`
+    pub fn viewport<T>(&self, viewport: &Viewport, frame: SuperFrame<B>, aux: &T) -> Viewport
+    where
+        P: Pass<B, T>,
+    {
+        let rect = match self.render_area {
+            RenderArea::Viewport => viewport.rect,
+            RenderArea::Full => Rect {
+                x: 0,
+                y: 0,
+                w: self.extent.width as u16,
+                h: self.extent.height as u16,
+            },
+            RenderArea::Fixed(rect) => rect,
+            RenderArea::Dynamic => self.pass.render_area(viewport.rect, frame.index(), aux),
+        };
+        Viewport {
+            rect,
+            depth: viewport.depth.clone(),
+        }
+    }
+
+    /// Set value the attachment is cleared with if this pass clears it.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `attachment`: attachment to set clear value for
+    /// - `clear`: new clear value
+    pub fn set_clear(&mut self, attachment: AttachmentRef, clear: ClearValue) {
+        for (value, _) in self.clears
+            .iter_mut()
+            .zip(&self.attachments)
+            .filter(|&(_, &a)| a == attachment)
+        {
+            *value = clear;
+        }
+    }
+
+    /// Check if the pass is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Enable or disable the pass.
+    /// Disabled pass records its renderpass without drawing commands.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Create empty storage for shader modules of each pipeline variant.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `cache`: shader modules shared between passes
+    pub(crate) fn shader_modules(&self, cache: &ShaderCache<B>) -> Vec<ShaderModules<B>>
+    where
+        P: PassDesc,
+    {
+        self.settings.shader_modules(&self.pass, cache)
+    }
+
+    /// Load shaders and fill descriptions of graphics pipelines for all variants.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `shaders`: storage for shader modules of each variant
+    /// - `specializations`: storage for specialization constants of each variant
+    /// - `device`: graphics device
+    pub(crate) fn pipeline_descs<'a>(
+        &'a self,
+        shaders: &'a mut [ShaderModules<B>],
+        specializations: &'a mut Vec<Vec<(Stage, Vec<Specialization>)>>,
+        device: &B::Device,
+    ) -> Result<Vec<GraphicsPipelineDesc<'a, B>>, CreationError>
+    where
+        P: PassShaders<B>,
+    {
+        self.settings.pipeline_descs(
+            &self.pass,
+            shaders,
+            specializations,
+            &self.pipeline_layout,
+            &self.renderpass,
+            device,
+        )
+    }
+
+    /// Replace graphics pipelines of the pass destroying old ones.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `pipelines`: new pipeline for each variant
+    /// - `device`: graphics device
+    pub(crate) fn set_graphics_pipelines(
+        &mut self,
+        pipelines: Vec<B::GraphicsPipeline>,
+        device: &B::Device,
+    ) {
+        for pipeline in replace(&mut self.graphics_pipelines, pipelines) {
+            device.destroy_graphics_pipeline(pipeline);
+        }
+    }
+
+    /// Records commands for the disabled pass.
+    /// Renderpass is still executed without drawing commands to keep clearing, load and store
+    /// operations and layout transitions of the attachments intact.
+    /// After that the forwarded attachment is copied (see `PassBuilder::with_forward`).
+    ///
+    /// ### Parameters:
+    ///
+    /// - `cbuf`: command buffer to record commands to
+    /// - `images`: all images created by the graph
+    /// - `rect`: area to draw in
+    /// - `frame`: specifies which framebuffer and images to use
+    ///
+    /// ### Type parameters:
+    ///
+    /// - `C`: hal `Capability`
+    pub fn draw_disabled<C, I>(
+        &self,
+        cbuf: &mut CommandBuffer<B, C>,
+        images: &[I],
+        rect: Rect,
+        frame: SuperFrame<B>,
+    ) where
+        C: Supports<Graphics>,
+        I: Borrow<B::Image>,
+    {
+        // Render pass ends as soon as encoder is dropped
+        cbuf.begin_render_pass_inline(
+            &self.renderpass,
+            pick(&self.framebuffer, &frame),
+            rect,
+            &self.clears,
+        );
+
+        if let Some(((ref from, from_layout), (ref to, to_layout))) = self.forward {
+            let from = images[from.start + frame.index()].borrow();
+            let to = images[to.start + frame.index()].borrow();
+            let range = SubresourceRange {
+                aspects: Aspects::COLOR,
+                levels: 0..1,
+                layers: 0..1,
+            };
+
+            // Wait for the renderpass to store the attachment before overwriting it
+            // and transition both images to the layouts optimal for copying
+            cbuf.pipeline_barrier(
+                (PipelineStage::FRAGMENT_SHADER | PipelineStage::COLOR_ATTACHMENT_OUTPUT)
+                    ..PipelineStage::TRANSFER,
+                Dependencies::empty(),
+                &[
+                    Barrier::Image {
+                        states: (Access::SHADER_READ, from_layout)
+                            ..(Access::TRANSFER_READ, ImageLayout::TransferSrcOptimal),
+                        target: from,
+                        range: range.clone(),
+                    },
+                    Barrier::Image {
+                        states: (Access::COLOR_ATTACHMENT_WRITE, to_layout)
+                            ..(Access::TRANSFER_WRITE, ImageLayout::TransferDstOptimal),
+                        target: to,
+                        range: range.clone(),
+                    },
+                ],
+            );
+
+            let subresource = SubresourceLayers {
+                aspects: Aspects::COLOR,
+                level: 0,
+                layers: 0..1,
+            };
+            // Images have the same format and extent, so plain copy is enough
+            let origin = Offset { x: 0, y: 0, z: 0 };
+            cbuf.copy_image(
+                from,
+                ImageLayout::TransferSrcOptimal,
+                to,
+                ImageLayout::TransferDstOptimal,
+                &[
+                    ImageCopy {
+                        src_subresource: subresource.clone(),
+                        src_offset: origin,
+                        dst_subresource: subresource,
+                        dst_offset: origin,
+                        extent: Extent {
+                            depth: 1,
+                            ..self.extent
+                        },
+                    },
+                ],
+            );
+
+            // Return images to the layouts following passes expect them in
+            cbuf.pipeline_barrier(
+                PipelineStage::TRANSFER
+                    ..(PipelineStage::FRAGMENT_SHADER | PipelineStage::COLOR_ATTACHMENT_OUTPUT),
+                Dependencies::empty(),
+                &[
+                    Barrier::Image {
+                        states: (Access::TRANSFER_READ, ImageLayout::TransferSrcOptimal)
+                            ..(Access::SHADER_READ, from_layout),
+                        target: from,
+                        range: range.clone(),
+                    },
+                    Barrier::Image {
+                        states: (Access::TRANSFER_WRITE, ImageLayout::TransferDstOptimal)
+                            ..(Access::SHADER_READ | Access::COLOR_ATTACHMENT_WRITE, to_layout),
+                        target: to,
+                        range,
+                    },
+                ],
+            );
+        }
+    }
+
+    /// Records barriers for storage attachments of the pass.
+    /// Waits for previous passes to finish accessing the attachments
+    /// and transitions them to `General` layout on first use.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `cbuf`: command buffer to record commands to
+    /// - `images`: all images created by the graph
+    /// - `frame`: specifies which images to use
+    ///
+    /// ### Type parameters:
+    ///
+    /// - `C`: hal `Capability`
+    pub fn storage_barriers<C, I>(
+        &self,
+        cbuf: &mut CommandBuffer<B, C>,
+        images: &[I],
+        frame: SuperFrame<B>,
+    ) where
+        C: Supports<Graphics>,
+        I: Borrow<B::Image>,
+    {
+        if self.storage_barriers.is_empty() {
+            return;
+        }
+
+        let barriers = self.storage_barriers
+            .iter()
+            .map(|&(ref storage, ref states)| Barrier::Image {
+                states: states.clone(),
+                target: images[storage.start + frame.index()].borrow(),
+                range: SubresourceRange {
+                    aspects: Aspects::COLOR,
+                    levels: 0..1,
+                    layers: 0..1,
+                },
+            })
+            .collect::<SmallVec<[_; 8]>>();
+
+        let shaders = PipelineStage::VERTEX_SHADER | PipelineStage::FRAGMENT_SHADER;
+        cbuf.pipeline_barrier(
+            (shaders | PipelineStage::COLOR_ATTACHMENT_OUTPUT
+                | PipelineStage::LATE_FRAGMENT_TESTS)..shaders,
+            Dependencies::empty(),
+            &barriers,
+        );
+    }
+
+    /// Number of chunks the inner `Pass` splits its drawing commands into.
+    pub fn chunks<T>(&self, frame: SuperFrame<B>, aux: &T) -> usize
+    where
+        P: Pass<B, T>,
+    {
+        self.pass.chunks(frame.index(), aux)
+    }
+
+    /// Records single chunk of drawing commands into secondary command buffer from `pool`.
+    /// Binds pipeline and descriptor set with attachments and sets viewport and scissor
+    /// before executing `Pass::draw_chunk` of the inner `Pass`.
+    /// Can be called concurrently for different chunks.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `pool`: command pool exclusively owned by the calling thread
+    /// - `device`: graphics device
+    /// - `images`: all images created by the graph
+    /// - `samplers`: all samplers created by the graph
+    /// - `viewport`: portion of framebuffers to draw to
+    /// - `chunk`: index of the chunk to record
+    /// - `frame`: specifies which framebuffer and descriptor sets to use
+    /// - `aux`: auxiliary data for the inner `Pass`
+    ///
+    /// ### Type parameters:
+    ///
+    /// - `C`: hal `Capability`
+    pub fn draw_chunk<C, T, I>(
+        &self,
+        pool: &mut CommandPool<B, C>,
+        device: &B::Device,
+        images: &[I],
+        samplers: &[B::Sampler],
+        viewport: &Viewport,
+        chunk: usize,
+        frame: SuperFrame<B>,
+        aux: &T,
+    ) -> Submit<B, Graphics, OneShot, Secondary>
+    where
+        C: Supports<Graphics>,
+        P: Pass<B, T>,
+        I: Borrow<B::Image>,
+    {
+        let mut cbuf = pool.acquire_subpass_command_buffer::<OneShot>(false);
+        {
+            let subpass = Subpass {
+                index: 0,
+                main_pass: &*self.renderpass,
+            };
+            let mut encoder = cbuf.begin_inline(subpass, Some(pick(&self.framebuffer, &frame)));
+
+            // Secondary buffers don't inherit any state from the primary one
+            encoder.set_viewports(&[viewport.clone()]);
+            encoder.set_scissors(&[viewport.rect]);
+            encoder.bind_graphics_pipeline(&self.graphics_pipelines[0]);
+            if let Some((_, ref sets)) = self.attachment_sets {
+                encoder.bind_graphics_descriptor_sets(
+                    &self.pipeline_layout,
+                    self.descriptors.len(),
+                    Some(&sets[frame.index()]),
+                );
+            }
+
+            let inputs = self.pick_inputs(images, frame);
+            let samplers = self.pick_samplers(samplers);
+
+            // Record custom drawing calls
+            self.pass.draw_chunk(
+                &self.pipeline_layout,
+                &self.graphics_pipelines,
+                encoder,
+                device,
+                &inputs,
+                &samplers,
+                chunk,
+                frame.index(),
+                aux,
+            );
+        }
+        cbuf.finish()
+    }
+
+    /// Begins renderpass with secondary subpass contents and executes recorded chunks in it.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `cbuf`: primary command buffer to record commands to
+    /// - `rect`: area to draw in
+    /// - `frame`: specifies which framebuffer to use
+    /// - `chunks`: secondary command buffers recorded with `PassNode::draw_chunk` in order
+    ///
+    /// ### Type parameters:
+    ///
+    /// - `C`: hal `Capability`
+    pub fn execute_chunks<C, J>(
+        &self,
+        cbuf: &mut CommandBuffer<B, C>,
+        rect: Rect,
+        frame: SuperFrame<B>,
+        chunks: J,
+    ) where
+        C: Supports<Graphics>,
+        J: IntoIterator<Item = Submit<B, Graphics, OneShot, Secondary>>,
+    {
+        let mut encoder = cbuf.begin_render_pass_secondary(
+            &self.renderpass,
+            pick(&self.framebuffer, &frame),
+            rect,
+            &self.clears,
+        );
+        encoder.execute_commands(chunks);
+    }
+
+    fn pick_inputs<'a, I>(
+        &self,
+        images: &'a [I],
+        frame: SuperFrame<B>,
+    ) -> SmallVec<[&'a B::Image; 16]>
+    where
+        I: Borrow<B::Image>,
+    {
+        self.inputs
+            .get(frame.index())
+            .map_or(SmallVec::new(), |inputs| {
+                inputs
+                    .iter()
+                    .map(|&index| images[index].borrow())
+                    .collect()
+            })
+    }
+
+    fn pick_samplers<'a>(&self, samplers: &'a [B::Sampler]) -> SmallVec<[&'a B::Sampler; 16]> {
+        self.samplers
+            .iter()
+            .map(|&index| &samplers[index])
+            .collect()
+    }
+
     /// Dispose of all internal data created by the pass.
     ///
     /// Will call [`Pass::cleanup`], and destroy any locally created framebuffers, renderpasses,
@@ -399,14 +1118,129 @@ where
         P: Pass<B, T>,
     {
         self.pass.cleanup(&mut self.descriptors, device, aux);
+        for pool in self.descriptors {
+            pool.dispose(device);
+        }
+        if let Some((mut pool, sets)) = self.attachment_sets {
+            for set in sets {
+                pool.free(set);
+            }
+            pool.dispose(device);
+        }
         match self.framebuffer {
             SuperFramebuffer::Owned(framebuffers) => for framebuffer in framebuffers {
                 device.destroy_framebuffer(framebuffer);
             },
             _ => {}
         }
-        device.destroy_render_pass(self.renderpass);
-        device.destroy_graphics_pipeline(self.graphics_pipeline);
-        device.destroy_pipeline_layout(self.pipeline_layout);
+        for pipeline in self.graphics_pipelines {
+            device.destroy_graphics_pipeline(pipeline);
+        }
+        // Renderpass and pipeline layout may be shared with other passes
+        if let Ok(renderpass) = Arc::try_unwrap(self.renderpass) {
+            device.destroy_render_pass(renderpass);
+        }
+        if let Ok(pipeline_layout) = Arc::try_unwrap(self.pipeline_layout) {
+            device.destroy_pipeline_layout(pipeline_layout);
+        }
+    }
+}
+
+/// Load shaders and create graphics pipelines of all passes with a single call.
+/// Passes that use the same shader code share modules.
+///
+/// ### Parameters:
+///
+/// - `passes`: passes to create pipelines for
+/// - `parallel`: split pipelines between worker threads
+/// - `device`: graphics device
+///
+/// ### Returns
+///
+/// Pipelines of each pass or the first error encountered creating them.
+pub(crate) fn create_graphics_pipelines<B, P>(
+    passes: &[&PassNode<B, P>],
+    parallel: bool,
+    device: &B::Device,
+) -> Vec<Result<Vec<B::GraphicsPipeline>, CreationError>>
+where
+    B: Backend,
+    P: PassShaders<B>,
+{
+    let cache = ShaderCache::new();
+    let mut shaders = passes
+        .iter()
+        .map(|pass| pass.shader_modules(&cache))
+        .collect::<Vec<_>>();
+    let mut specializations = passes.iter().map(|_| Vec::new()).collect::<Vec<_>>();
+
+    let results = {
+        // Gather descriptions of all pipelines
+        let mut descs = Vec::new();
+        let mut ranges = Vec::new();
+        for ((pass, shaders), specializations) in
+            passes.iter().zip(&mut shaders).zip(&mut specializations)
+        {
+            ranges.push(
+                pass.pipeline_descs(shaders, specializations, device)
+                    .map(|pass_descs| {
+                        let start = descs.len();
+                        descs.extend(pass_descs);
+                        start..descs.len()
+                    }),
+            );
+        }
+
+        // TODO: Accept pipeline cache and expose its data for saving to disk
+        // when `gfx_hal` gets pipeline cache support. Pipelines are compiled from scratch for now.
+        debug!("Create {} graphics pipelines", descs.len());
+        let mut pipelines = if parallel && descs.len() > 1 {
+            // Backends compile pipelines passed to a single call sequentially
+            let threads = current_num_threads();
+            descs
+                .par_chunks((descs.len() + threads - 1) / threads)
+                .map(|descs| device.create_graphics_pipelines(descs))
+                .collect::<Vec<_>>()
+                .into_iter()
+                .flatten()
+                .map(Some)
+                .collect::<Vec<_>>()
+        } else {
+            device
+                .create_graphics_pipelines(&descs)
+                .into_iter()
+                .map(Some)
+                .collect::<Vec<_>>()
+        };
+
+        // Split pipelines between passes
+        ranges
+            .into_iter()
+            .map(|range| {
+                let mut created = Vec::new();
+                let mut error = None;
+                for pipeline in pipelines[range?].iter_mut().filter_map(Option::take) {
+                    match pipeline {
+                        Ok(pipeline) => created.push(pipeline),
+                        Err(err) => error = error.or(Some(err)),
+                    }
+                }
+                match error {
+                    Some(error) => {
+                        for pipeline in created {
+                            device.destroy_graphics_pipeline(pipeline);
+                        }
+                        Err(error)
+                    }
+                    None => Ok(created),
+                }
+            })
+            .collect()
+    };
+
+    for shaders in shaders.into_iter().flatten() {
+        shaders.dispose(device);
     }
+    cache.dispose(device);
+    results
 }
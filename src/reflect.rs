@@ -0,0 +1,525 @@
+//! Minimal SPIR-V reflection.
+//! Finds descriptor bindings, vertex inputs and push constants used by a shader module
+//! to validate them against the layout declared by the `PassDesc`.
+//!
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use gfx_hal::device::ShaderError;
+use gfx_hal::pso::{DescriptorSetLayoutBinding, DescriptorType, Location, ShaderStageFlags};
+
+const MAGIC: u32 = 0x07230203;
+
+// Opcodes
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+// Decorations
+const DECORATION_BLOCK: u32 = 2;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+// Storage classes
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_INPUT: u32 = 1;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_PUSH_CONSTANT: u32 = 9;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+// Image dimensions
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+/// Resources and inputs used by a shader module.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShaderInterface {
+    /// Stages of all entry points of the module.
+    pub stages: ShaderStageFlags,
+    /// Descriptor bindings grouped by set index.
+    pub sets: Vec<Vec<DescriptorSetLayoutBinding>>,
+    /// Locations of vertex shader inputs.
+    pub inputs: Vec<Location>,
+    /// Push constant ranges used by the module.
+    pub push_constants: Vec<(ShaderStageFlags, Range<u32>)>,
+}
+
+impl ShaderInterface {
+    /// Reflect SPIR-V module.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `spirv`: SPIR-V module
+    pub fn reflect(spirv: &[u8]) -> Result<Self, ShaderError> {
+        let words = words(spirv).ok_or_else(|| invalid("not a SPIR-V module"))?;
+        Module::parse(&words)
+            .map(|module| module.interface())
+            .ok_or_else(|| invalid("malformed SPIR-V module"))
+    }
+
+    /// Check that every resource used by the shader is declared in the `layout`.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `layout`: interface declared for the pipeline. `stages` field is ignored.
+    pub fn check(&self, layout: &ShaderInterface) -> Result<(), ShaderError> {
+        for (set, bindings) in self.sets.iter().enumerate() {
+            for binding in bindings {
+                let declared = layout
+                    .sets
+                    .get(set)
+                    .and_then(|declared| declared.iter().find(|b| b.binding == binding.binding))
+                    .ok_or_else(|| {
+                        mismatch(format!(
+                            "Binding {} of set {} is not declared",
+                            binding.binding, set
+                        ))
+                    })?;
+                if declared.ty != binding.ty {
+                    return Err(mismatch(format!(
+                        "Binding {} of set {} is declared as {:?} but shader uses {:?}",
+                        binding.binding, set, declared.ty, binding.ty
+                    )));
+                }
+                if declared.count < binding.count {
+                    return Err(mismatch(format!(
+                        "Binding {} of set {} is declared with {} descriptors but shader uses {}",
+                        binding.binding, set, declared.count, binding.count
+                    )));
+                }
+                if !declared.stage_flags.contains(binding.stage_flags) {
+                    return Err(mismatch(format!(
+                        "Binding {} of set {} is not declared for stages {:?}",
+                        binding.binding, set, binding.stage_flags
+                    )));
+                }
+            }
+        }
+
+        for location in &self.inputs {
+            if !layout.inputs.contains(location) {
+                return Err(mismatch(format!(
+                    "Vertex input at location {} is not declared",
+                    location
+                )));
+            }
+        }
+
+        for &(stages, ref range) in &self.push_constants {
+            let covered = layout.push_constants.iter().any(|&(declared, ref declared_range)| {
+                declared.contains(stages) && declared_range.start <= range.start
+                    && declared_range.end >= range.end
+            });
+            if !covered {
+                return Err(mismatch(format!(
+                    "Push constants {:?} for stages {:?} are not declared",
+                    range, stages
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn invalid(message: &str) -> ShaderError {
+    ShaderError::CompilationFailed(message.into())
+}
+
+fn mismatch(message: String) -> ShaderError {
+    ShaderError::InterfaceMismatch(message)
+}
+
+/// Split bytes into words fixing endianness.
+fn words(spirv: &[u8]) -> Option<Vec<u32>> {
+    if spirv.len() % 4 != 0 || spirv.len() < 20 {
+        return None;
+    }
+    let le = spirv
+        .chunks(4)
+        .map(|b| b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24)
+        .collect::<Vec<_>>();
+    if le[0] == MAGIC {
+        Some(le)
+    } else if le[0].swap_bytes() == MAGIC {
+        Some(le.into_iter().map(u32::swap_bytes).collect())
+    } else {
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Type {
+    Scalar(u32),
+    Vector(u32, u32),
+    Matrix(u32, u32),
+    Image(u32, u32),
+    Sampler,
+    SampledImage,
+    Array(u32, u32),
+    RuntimeArray(u32),
+    Pointer(u32),
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Decorations {
+    set: Option<u32>,
+    binding: Option<u32>,
+    location: Option<u32>,
+    built_in: bool,
+    block: bool,
+    buffer_block: bool,
+    array_stride: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct MemberDecorations {
+    offset: u32,
+    matrix_stride: Option<u32>,
+}
+
+#[derive(Debug)]
+struct Module {
+    stages: ShaderStageFlags,
+    types: HashMap<u32, Type>,
+    structs: HashMap<u32, Vec<u32>>,
+    constants: HashMap<u32, u32>,
+    decorations: HashMap<u32, Decorations>,
+    members: HashMap<(u32, u32), MemberDecorations>,
+    variables: Vec<(u32, u32, u32)>,
+}
+
+impl Module {
+    fn parse(words: &[u32]) -> Option<Self> {
+        let mut module = Module {
+            stages: ShaderStageFlags::empty(),
+            types: HashMap::new(),
+            structs: HashMap::new(),
+            constants: HashMap::new(),
+            decorations: HashMap::new(),
+            members: HashMap::new(),
+            variables: Vec::new(),
+        };
+        let mut offset = 5;
+        while offset < words.len() {
+            let count = (words[offset] >> 16) as usize;
+            let opcode = words[offset] & 0xffff;
+            if count == 0 || offset + count > words.len() {
+                return None;
+            }
+            let ops = &words[offset + 1..offset + count];
+            offset += count;
+
+            match opcode {
+                OP_ENTRY_POINT => {
+                    module.stages |= match *ops.get(0)? {
+                        0 => ShaderStageFlags::VERTEX,
+                        1 => ShaderStageFlags::HULL,
+                        2 => ShaderStageFlags::DOMAIN,
+                        3 => ShaderStageFlags::GEOMETRY,
+                        4 => ShaderStageFlags::FRAGMENT,
+                        5 => ShaderStageFlags::COMPUTE,
+                        _ => ShaderStageFlags::empty(),
+                    };
+                }
+                OP_TYPE_INT | OP_TYPE_FLOAT if ops.len() >= 2 => {
+                    module.types.insert(ops[0], Type::Scalar(ops[1] / 8));
+                }
+                OP_TYPE_VECTOR if ops.len() >= 3 => {
+                    module.types.insert(ops[0], Type::Vector(ops[1], ops[2]));
+                }
+                OP_TYPE_MATRIX if ops.len() >= 3 => {
+                    module.types.insert(ops[0], Type::Matrix(ops[1], ops[2]));
+                }
+                OP_TYPE_IMAGE if ops.len() >= 7 => {
+                    module.types.insert(ops[0], Type::Image(ops[2], ops[6]));
+                }
+                OP_TYPE_SAMPLER if ops.len() >= 1 => {
+                    module.types.insert(ops[0], Type::Sampler);
+                }
+                OP_TYPE_SAMPLED_IMAGE if ops.len() >= 1 => {
+                    module.types.insert(ops[0], Type::SampledImage);
+                }
+                OP_TYPE_ARRAY if ops.len() >= 3 => {
+                    module.types.insert(ops[0], Type::Array(ops[1], ops[2]));
+                }
+                OP_TYPE_RUNTIME_ARRAY if ops.len() >= 2 => {
+                    module.types.insert(ops[0], Type::RuntimeArray(ops[1]));
+                }
+                OP_TYPE_STRUCT if ops.len() >= 1 => {
+                    module.structs.insert(ops[0], ops[1..].to_vec());
+                }
+                OP_TYPE_POINTER if ops.len() >= 3 => {
+                    module.types.insert(ops[0], Type::Pointer(ops[2]));
+                }
+                OP_CONSTANT if ops.len() >= 3 => {
+                    module.constants.insert(ops[1], ops[2]);
+                }
+                OP_VARIABLE if ops.len() >= 3 => {
+                    module.variables.push((ops[0], ops[1], ops[2]));
+                }
+                OP_DECORATE if ops.len() >= 2 => {
+                    let decorations = module
+                        .decorations
+                        .entry(ops[0])
+                        .or_insert_with(Default::default);
+                    let value = ops.get(2).cloned();
+                    match ops[1] {
+                        DECORATION_BLOCK => decorations.block = true,
+                        DECORATION_BUFFER_BLOCK => decorations.buffer_block = true,
+                        DECORATION_ARRAY_STRIDE => decorations.array_stride = value,
+                        DECORATION_BUILT_IN => decorations.built_in = true,
+                        DECORATION_LOCATION => decorations.location = value,
+                        DECORATION_BINDING => decorations.binding = value,
+                        DECORATION_DESCRIPTOR_SET => decorations.set = value,
+                        _ => {}
+                    }
+                }
+                OP_MEMBER_DECORATE if ops.len() >= 3 => {
+                    let member = module.members
+                        .entry((ops[0], ops[1]))
+                        .or_insert_with(Default::default);
+                    let value = ops.get(3).cloned();
+                    match ops[2] {
+                        DECORATION_OFFSET => member.offset = value.unwrap_or(0),
+                        DECORATION_MATRIX_STRIDE => member.matrix_stride = value,
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        Some(module)
+    }
+
+    fn interface(&self) -> ShaderInterface {
+        let mut interface = ShaderInterface {
+            stages: self.stages,
+            sets: Vec::new(),
+            inputs: Vec::new(),
+            push_constants: Vec::new(),
+        };
+
+        for &(pointer, id, class) in &self.variables {
+            let ty = match self.types.get(&pointer) {
+                Some(&Type::Pointer(ty)) => ty,
+                _ => continue,
+            };
+            let decorations = self.decorations.get(&id).cloned().unwrap_or_default();
+
+            match class {
+                STORAGE_INPUT if self.stages.contains(ShaderStageFlags::VERTEX) => {
+                    if let (false, Some(location)) = (decorations.built_in, decorations.location) {
+                        let count = self.locations(ty);
+                        interface.inputs.extend(location..location + count);
+                    }
+                }
+                STORAGE_PUSH_CONSTANT => {
+                    interface
+                        .push_constants
+                        .push((self.stages, self.struct_range(ty)));
+                }
+                STORAGE_UNIFORM_CONSTANT | STORAGE_UNIFORM | STORAGE_STORAGE_BUFFER => {
+                    let (ty, count) = self.unwrap_array(ty);
+                    let descriptor = match self.descriptor_type(ty, class) {
+                        Some(descriptor) => descriptor,
+                        None => continue,
+                    };
+                    let set = decorations.set.unwrap_or(0) as usize;
+                    if interface.sets.len() <= set {
+                        interface.sets.resize(set + 1, Vec::new());
+                    }
+                    interface.sets[set].push(DescriptorSetLayoutBinding {
+                        binding: decorations.binding.unwrap_or(0) as _,
+                        ty: descriptor,
+                        count: count as usize,
+                        stage_flags: self.stages,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        interface.inputs.sort();
+        interface
+    }
+
+    /// Find descriptor type for a resource type.
+    fn descriptor_type(&self, ty: u32, class: u32) -> Option<DescriptorType> {
+        let decorations = self.decorations.get(&ty).cloned().unwrap_or_default();
+        match (class, self.types.get(&ty)) {
+            (STORAGE_UNIFORM, None) if decorations.buffer_block => {
+                Some(DescriptorType::StorageBuffer)
+            }
+            (STORAGE_UNIFORM, None) if decorations.block => Some(DescriptorType::UniformBuffer),
+            (STORAGE_STORAGE_BUFFER, None) => Some(DescriptorType::StorageBuffer),
+            (STORAGE_UNIFORM_CONSTANT, Some(&Type::Sampler)) => Some(DescriptorType::Sampler),
+            (STORAGE_UNIFORM_CONSTANT, Some(&Type::Image(dim, sampled))) => {
+                Some(match (dim, sampled) {
+                    (DIM_SUBPASS_DATA, _) => DescriptorType::InputAttachment,
+                    (DIM_BUFFER, 2) => DescriptorType::StorageTexelBuffer,
+                    (DIM_BUFFER, _) => DescriptorType::UniformTexelBuffer,
+                    (_, 2) => DescriptorType::StorageImage,
+                    _ => DescriptorType::SampledImage,
+                })
+            }
+            // Combined image-samplers can't be declared yet.
+            // Report them as sampled images so mismatch will be found.
+            (STORAGE_UNIFORM_CONSTANT, Some(&Type::SampledImage)) => {
+                Some(DescriptorType::SampledImage)
+            }
+            _ => None,
+        }
+    }
+
+    /// Strip arrays of descriptors returning element type and number of descriptors.
+    fn unwrap_array(&self, ty: u32) -> (u32, u32) {
+        match self.types.get(&ty) {
+            Some(&Type::Array(element, length)) => {
+                let (element, count) = self.unwrap_array(element);
+                (element, count * self.constants.get(&length).cloned().unwrap_or(1))
+            }
+            Some(&Type::RuntimeArray(element)) => self.unwrap_array(element),
+            _ => (ty, 1),
+        }
+    }
+
+    /// Number of locations occupied by an input of the type.
+    fn locations(&self, ty: u32) -> u32 {
+        match self.types.get(&ty) {
+            Some(&Type::Matrix(_, columns)) => columns,
+            Some(&Type::Array(element, length)) => {
+                self.locations(element) * self.constants.get(&length).cloned().unwrap_or(1)
+            }
+            _ => 1,
+        }
+    }
+
+    /// Range of bytes occupied by struct members.
+    fn struct_range(&self, ty: u32) -> Range<u32> {
+        let members = match self.structs.get(&ty) {
+            Some(members) => members,
+            None => return 0..self.size(ty, None),
+        };
+        members
+            .iter()
+            .enumerate()
+            .map(|(index, &member)| {
+                let decorations = self.members
+                    .get(&(ty, index as u32))
+                    .cloned()
+                    .unwrap_or_default();
+                let size = self.size(member, decorations.matrix_stride);
+                decorations.offset..decorations.offset + size
+            })
+            .fold(None, |range: Option<Range<u32>>, member| {
+                Some(match range {
+                    Some(range) => range.start.min(member.start)..range.end.max(member.end),
+                    None => member,
+                })
+            })
+            .unwrap_or(0..0)
+    }
+
+    /// Size of the type in bytes.
+    fn size(&self, ty: u32, matrix_stride: Option<u32>) -> u32 {
+        if self.structs.contains_key(&ty) {
+            return self.struct_range(ty).end;
+        }
+        match self.types.get(&ty) {
+            Some(&Type::Scalar(size)) => size,
+            Some(&Type::Vector(element, count)) => self.size(element, None) * count,
+            Some(&Type::Matrix(column, count)) => {
+                matrix_stride.unwrap_or_else(|| self.size(column, None)) * count
+            }
+            Some(&Type::Array(element, length)) => {
+                let length = self.constants.get(&length).cloned().unwrap_or(1);
+                let stride = self.decorations
+                    .get(&ty)
+                    .and_then(|decorations| decorations.array_stride)
+                    .unwrap_or_else(|| self.size(element, matrix_stride));
+                stride * length
+            }
+            _ => 0,
+        }
+    }
+}
+
+#[test]
+fn test_reflect_flat() {
+    let vertex = ShaderInterface::reflect(include_bytes!("../examples/src/flat/vert.spv")).unwrap();
+    assert_eq!(vertex.stages, ShaderStageFlags::VERTEX);
+    assert_eq!(vertex.inputs, vec![0, 1]);
+    assert_eq!(
+        vertex.sets,
+        vec![
+            vec![
+                DescriptorSetLayoutBinding {
+                    binding: 0,
+                    ty: DescriptorType::UniformBuffer,
+                    count: 1,
+                    stage_flags: ShaderStageFlags::VERTEX,
+                },
+            ],
+        ]
+    );
+    assert!(vertex.push_constants.is_empty());
+
+    let mut layout = vertex.clone();
+    assert!(vertex.check(&layout).is_ok());
+    layout.inputs = vec![0];
+    assert!(vertex.check(&layout).is_err());
+    layout.inputs = vec![0, 1];
+    layout.sets[0][0].stage_flags = ShaderStageFlags::FRAGMENT;
+    assert!(vertex.check(&layout).is_err());
+    layout.sets[0][0].stage_flags = ShaderStageFlags::GRAPHICS;
+    layout.sets[0][0].ty = DescriptorType::StorageBuffer;
+    assert!(vertex.check(&layout).is_err());
+}
+
+#[test]
+fn test_reflect_storage_images() {
+    let fragment =
+        ShaderInterface::reflect(include_bytes!("../examples/src/pbm_deferred/second.frag.spv"))
+            .unwrap();
+    assert_eq!(fragment.stages, ShaderStageFlags::FRAGMENT);
+    assert!(fragment.inputs.is_empty());
+    let types = |set: usize| {
+        let mut types = fragment.sets[set]
+            .iter()
+            .map(|binding| (binding.binding, binding.ty))
+            .collect::<Vec<_>>();
+        types.sort_by_key(|&(binding, _)| binding);
+        types
+    };
+    assert_eq!(types(0), vec![(0, DescriptorType::UniformBuffer)]);
+    assert_eq!(
+        types(1),
+        vec![
+            (0, DescriptorType::StorageImage),
+            (1, DescriptorType::StorageImage),
+            (2, DescriptorType::StorageImage),
+            (3, DescriptorType::StorageImage),
+        ]
+    );
+}
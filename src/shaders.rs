@@ -0,0 +1,193 @@
+//! Defines storage for shader modules loaded by `Pass`es.
+//!
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Index;
+use std::rc::Rc;
+
+use gfx_hal::{Backend, Device};
+use gfx_hal::device::ShaderError;
+use smallvec::SmallVec;
+
+#[cfg(feature = "reflection")]
+use reflect::ShaderInterface;
+
+/// Shader modules created from SPIR-V keyed by the code.
+/// Shared by all `ShaderModules` during graph build or shaders reload,
+/// so passes that use the same shaders create modules only once.
+#[derive(Debug)]
+pub(crate) struct ShaderCache<B: Backend> {
+    modules: Rc<RefCell<HashMap<Vec<u8>, Rc<B::ShaderModule>>>>,
+}
+
+impl<B> ShaderCache<B>
+where
+    B: Backend,
+{
+    /// Create empty cache.
+    pub(crate) fn new() -> Self {
+        ShaderCache {
+            modules: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    /// Create empty storage for shader modules that uses this cache.
+    pub(crate) fn modules(&self) -> ShaderModules<B> {
+        ShaderModules {
+            modules: SmallVec::new(),
+            cache: ShaderCache {
+                modules: self.modules.clone(),
+            },
+            #[cfg(feature = "reflection")]
+            layout: None,
+        }
+    }
+
+    /// Destroy cached modules if they aren't used anymore.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: graphics device
+    pub(crate) fn dispose(self, device: &B::Device) {
+        if let Ok(modules) = Rc::try_unwrap(self.modules) {
+            for (_, module) in modules.into_inner() {
+                if let Ok(module) = Rc::try_unwrap(module) {
+                    device.destroy_shader_module(module);
+                }
+            }
+        }
+    }
+}
+
+/// Shader modules loaded for a single pipeline.
+/// All modules are destroyed by the `Graph` after pipelines are created.
+/// Modules created from the same SPIR-V code are shared between passes.
+///
+/// With `reflection` feature enabled modules created from SPIR-V with `ShaderModules::create`
+/// are checked to use only resources declared by the `PassDesc`.
+///
+/// ### Type parameters:
+///
+/// - `B`: hal `Backend`
+#[derive(Debug)]
+pub struct ShaderModules<B: Backend> {
+    modules: SmallVec<[Rc<B::ShaderModule>; 5]>,
+    cache: ShaderCache<B>,
+    #[cfg(feature = "reflection")]
+    layout: Option<ShaderInterface>,
+}
+
+impl<B> ShaderModules<B>
+where
+    B: Backend,
+{
+    /// Create empty storage.
+    pub fn new() -> Self {
+        ShaderCache::new().modules()
+    }
+
+    /// Check modules created from SPIR-V against the layout.
+    #[cfg(feature = "reflection")]
+    pub(crate) fn set_layout(&mut self, layout: ShaderInterface) {
+        self.layout = Some(layout);
+    }
+
+    /// Create shader module from SPIR-V.
+    /// Module created earlier from the same code is reused.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `spirv`: SPIR-V module
+    /// - `device`: graphics device
+    ///
+    /// ### Returns
+    ///
+    /// Index of the created module.
+    pub fn create(&mut self, spirv: &[u8], device: &B::Device) -> Result<usize, ShaderError> {
+        #[cfg(feature = "reflection")]
+        {
+            if let Some(ref layout) = self.layout {
+                ShaderInterface::reflect(spirv)?.check(layout)?;
+            }
+        }
+
+        let module = match self.cache.modules.borrow().get(spirv) {
+            Some(module) => Some(module.clone()),
+            None => None,
+        };
+        let module = match module {
+            Some(module) => module,
+            None => {
+                let module = Rc::new(device.create_shader_module(spirv)?);
+                self.cache
+                    .modules
+                    .borrow_mut()
+                    .insert(spirv.to_vec(), module.clone());
+                module
+            }
+        };
+        self.modules.push(module);
+        Ok(self.modules.len() - 1)
+    }
+
+    /// Add shader module created by the pass.
+    /// Such modules are not checked.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `module`: shader module
+    ///
+    /// ### Returns
+    ///
+    /// Index of the module.
+    pub fn push(&mut self, module: B::ShaderModule) -> usize {
+        self.modules.push(Rc::new(module));
+        self.modules.len() - 1
+    }
+
+    /// Destroy modules that aren't shared through the cache.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `device`: graphics device
+    pub(crate) fn dispose(self, device: &B::Device) {
+        // Modules from the cache are destroyed along with the cache
+        for module in self.modules {
+            if let Ok(module) = Rc::try_unwrap(module) {
+                device.destroy_shader_module(module);
+            }
+        }
+        self.cache.dispose(device);
+    }
+
+    /// Number of modules.
+    pub fn len(&self) -> usize {
+        self.modules.len()
+    }
+
+    /// Check if there are no modules.
+    pub fn is_empty(&self) -> bool {
+        self.modules.is_empty()
+    }
+}
+
+impl<B> Default for ShaderModules<B>
+where
+    B: Backend,
+{
+    fn default() -> Self {
+        ShaderModules::new()
+    }
+}
+
+impl<B> Index<usize> for ShaderModules<B>
+where
+    B: Backend,
+{
+    type Output = B::ShaderModule;
+
+    fn index(&self, index: usize) -> &B::ShaderModule {
+        &self.modules[index]
+    }
+}
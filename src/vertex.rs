@@ -0,0 +1,112 @@
+//! Defines vertex formats required by `Pass`es.
+//!
+
+use gfx_hal::format::Format;
+use gfx_hal::pso::{ElemStride, Element, InstanceRate, Location};
+
+/// Single vertex attribute in a vertex buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VertexAttribute {
+    /// Shader location of the attribute.
+    /// `None` means next location after the previous attribute
+    /// or `0` for the very first attribute.
+    pub location: Option<Location>,
+    /// Format and offset of the attribute.
+    pub element: Element<Format>,
+}
+
+impl VertexAttribute {
+    /// Attribute placed at next location after the previous attribute.
+    pub fn new(format: Format, offset: u32) -> Self {
+        VertexAttribute {
+            location: None,
+            element: Element { format, offset },
+        }
+    }
+
+    /// Attribute placed at explicit location.
+    pub fn with_location(location: Location, format: Format, offset: u32) -> Self {
+        VertexAttribute {
+            location: Some(location),
+            element: Element { format, offset },
+        }
+    }
+}
+
+/// Format of a vertex buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VertexFormat<'a> {
+    /// Attributes of the vertex buffer.
+    pub attributes: &'a [VertexAttribute],
+    /// Size of one vertex (or one instance) in the buffer.
+    pub stride: ElemStride,
+    /// Input rate. `0` means the buffer is advanced per vertex,
+    /// `N` means the buffer is advanced once per `N` instances.
+    pub rate: InstanceRate,
+}
+
+impl<'a> VertexFormat<'a> {
+    /// Format of buffer advanced per vertex.
+    pub fn per_vertex(attributes: &'a [VertexAttribute], stride: ElemStride) -> Self {
+        VertexFormat {
+            attributes,
+            stride,
+            rate: 0,
+        }
+    }
+
+    /// Format of buffer advanced per instance.
+    pub fn per_instance(attributes: &'a [VertexAttribute], stride: ElemStride) -> Self {
+        VertexFormat {
+            attributes,
+            stride,
+            rate: 1,
+        }
+    }
+}
+
+/// Type with statically known vertex format.
+/// Can be derived for `#[repr(C)]` structs with `xfg-derive`.
+pub trait Vertex {
+    /// Format of the vertex buffer filled with values of this type.
+    const FORMAT: VertexFormat<'static>;
+}
+
+/// Resolve shader locations of all attributes of all vertex buffers.
+/// Returns location that is used by more than one attribute if any.
+pub(crate) fn attribute_locations(vertices: &[VertexFormat]) -> Result<Vec<Location>, Location> {
+    let mut locations: Vec<Location> = Vec::new();
+    for attribute in vertices.iter().flat_map(|format| format.attributes) {
+        let location = attribute
+            .location
+            .unwrap_or_else(|| locations.last().map_or(0, |&last| last + 1));
+        if locations.contains(&location) {
+            return Err(location);
+        }
+        locations.push(location);
+    }
+    Ok(locations)
+}
+
+#[test]
+fn test_attribute_locations() {
+    let position = VertexAttribute::new(Format::Rgb32Float, 0);
+    let normal = VertexAttribute::new(Format::Rgb32Float, 12);
+    let transform = VertexAttribute::with_location(4, Format::Rgba32Float, 0);
+    let overlapping = VertexAttribute::with_location(1, Format::Rgba32Float, 16);
+
+    let vertex_attributes = [position, normal];
+    let vertex = VertexFormat::per_vertex(&vertex_attributes, 24);
+
+    let instance_attributes = [transform, position];
+    let instance = VertexFormat::per_instance(&instance_attributes, 32);
+    assert_eq!(attribute_locations(&[vertex, instance]), Ok(vec![0, 1, 4, 5]));
+
+    let instance_attributes = [transform, transform];
+    let instance = VertexFormat::per_instance(&instance_attributes, 32);
+    assert_eq!(attribute_locations(&[vertex, instance]), Err(4));
+
+    let instance_attributes = [overlapping];
+    let instance = VertexFormat::per_instance(&instance_attributes, 32);
+    assert_eq!(attribute_locations(&[vertex, instance]), Err(1));
+}
@@ -0,0 +1,141 @@
+//! Shader files watching for `Graph::reload_pass_shaders`.
+//!
+//! Files are polled for modification time on each check, so no platform specific
+//! notification mechanism is required. Intended to be used during development only.
+//!
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use gfx_hal::Backend;
+use gfx_hal::pso::CreationError;
+
+use graph::Graph;
+use pass::PassShaders;
+
+/// Watches shader files of the passes and reloads passes which files were modified.
+///
+/// ### Example:
+///
+/// ```ignore
+/// let mut watcher = ShaderWatcher::new()
+///     .with_file("DrawFlat", "shaders/flat.vert.spv")
+///     .with_file("DrawFlat", "shaders/flat.frag.spv");
+///
+/// // Once per frame, while the device is idle
+/// watcher.reload(&mut graph, &device)?;
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ShaderWatcher {
+    files: Vec<WatchedFile>,
+}
+
+#[derive(Clone, Debug)]
+struct WatchedFile {
+    pass: String,
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl ShaderWatcher {
+    /// Create watcher without files.
+    pub fn new() -> Self {
+        ShaderWatcher::default()
+    }
+
+    /// Watch the file the shaders of the pass are loaded from.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `pass`: name of the pass as returned by `PassDesc::name`
+    /// - `path`: path to the shader file
+    pub fn with_file<S, F>(mut self, pass: S, path: F) -> Self
+    where
+        S: Into<String>,
+        F: Into<PathBuf>,
+    {
+        self.add_file(pass, path);
+        self
+    }
+
+    /// Watch the file the shaders of the pass are loaded from.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `pass`: name of the pass as returned by `PassDesc::name`
+    /// - `path`: path to the shader file
+    pub fn add_file<S, F>(&mut self, pass: S, path: F) -> &mut Self
+    where
+        S: Into<String>,
+        F: Into<PathBuf>,
+    {
+        let path = path.into();
+        let modified = modified(&path).ok();
+        self.files.push(WatchedFile {
+            pass: pass.into(),
+            path,
+            modified,
+        });
+        self
+    }
+
+    /// Check the files for modifications since they were added or checked last time.
+    ///
+    /// ### Returns
+    ///
+    /// Names of the passes which files were modified, each name is reported once.
+    pub fn changed(&mut self) -> Vec<String> {
+        let mut changed = Vec::new();
+        for file in &mut self.files {
+            let modified = match modified(&file.path) {
+                Ok(modified) => Some(modified),
+                Err(error) => {
+                    // File can be missing for a moment while being rewritten
+                    debug!("Can't query modification time of {:?}: {}", file.path, error);
+                    continue;
+                }
+            };
+            if modified != file.modified {
+                file.modified = modified;
+                if !changed.contains(&file.pass) {
+                    changed.push(file.pass.clone());
+                }
+            }
+        }
+        changed
+    }
+
+    /// Reload shaders of the passes which files were modified
+    /// with `Graph::reload_pass_shaders`, see `Graph::reload_shaders` for restrictions.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `graph`: graph the passes belong to
+    /// - `device`: graphics device
+    ///
+    /// ### Returns
+    ///
+    /// First error encountered. All modified passes are reloaded regardless.
+    pub fn reload<B, I, P>(
+        &mut self,
+        graph: &mut Graph<B, I, P>,
+        device: &B::Device,
+    ) -> Result<(), CreationError>
+    where
+        B: Backend,
+        P: PassShaders<B>,
+    {
+        let mut result = Ok(());
+        for pass in self.changed() {
+            info!("Reload shaders of pass {}", pass);
+            result = result.and(graph.reload_pass_shaders(&pass, device));
+        }
+        result
+    }
+}
+
+fn modified(path: &Path) -> io::Result<SystemTime> {
+    fs::metadata(path)?.modified()
+}